@@ -1,103 +1,119 @@
-use parking_lot::RwLock;
 use statemachine::{
+    action::Actions,
+    collection::Collection,
+    definition::Definition,
+    machine::StateId,
+    observer::Observer,
+    schema,
     serde_state::*,
-    state::State,
-    status::{InputStatus, OutputStatus},
+    session::{Session, StepResult},
+    source::{FileSource, LoadError, Sources},
+    value::Value,
 };
-use std::{error::Error as StdError, fs::File, io::Read, sync::Arc};
+use std::{fs, sync::Arc, time::Duration};
 
-fn run(root: Arc<RwLock<State>>) {
-    let mut current_state: Arc<RwLock<State>> = root.clone();
-    loop {
-        let output_status: OutputStatus;
-        let input_status: InputStatus;
-        {
-            {
-                let mut current_state_ref = current_state.write();
-                output_status = current_state_ref.output().unwrap();
-            }
+//tells what the user did, the loop in run only shows the prompts
+struct Printer;
 
-            if output_status.state_changed {
-                if let Some(state) = output_status.state {
-                    current_state = state;
-                    continue;
-                }
-                if output_status.submit {
-                    println!(
-                        "submitting on output from state {}\n",
-                        current_state.read().get_name()
-                    );
-                    let collections = current_state.write().collect().unwrap().unwrap();
-                    println!("{:?}", collections);
-                    break;
-                }
-            }
+impl Observer for Printer {
+    fn on_back(&self, session: &Session, state: StateId) {
+        println!("went back from {}", session.get_machine().get_name(state));
+    }
 
-            println!("{:?}", output_status.output);
+    fn on_submit(&self, session: &Session, collections: &[Collection]) {
+        println!(
+            "submitted from state {}\n",
+            session.get_machine().get_name(session.get_state())
+        );
+        println!("{:?}", collections);
+    }
+}
 
-            let mut current_state_ref = current_state.write();
+//the action state.yaml names on Child31. dates are written as yyyy-mm-dd,
+//so comparing them as text compares them as dates
+fn remember(collections: &[Collection]) -> Result<(), String> {
+    let too_old = collections
+        .iter()
+        .flat_map(|x| &x.context_collections)
+        .any(|x| matches!(&x.value, Value::Date(date) if date.to_string().as_str() < "2000-01-01"));
+    if too_old {
+        return Err(String::from("Nothing before 2000 is remembered"));
+    }
+    Ok(())
+}
 
-            let mut input = String::new();
-            std::io::stdin()
-                .read_line(&mut input)
-                .expect("error: unable to read user input");
-            if let Some('\n') = input.chars().next_back() {
-                input.pop();
-            }
-            if let Some('\r') = input.chars().next_back() {
-                input.pop();
+fn run(definition: Arc<Definition>) {
+    let mut session = Session::new(definition);
+    session.add_observer(Printer);
+    let mut step_result = session.prompt().unwrap();
+    loop {
+        match step_result {
+            StepResult::Submitted(_) => break,
+            StepResult::Rejected(output, reason) => {
+                println!("input not recognized: {}", reason.unwrap_or_default());
+                println!("{:?}", output);
             }
-            if input == "back" {
-                input_status = current_state_ref.back();
-            } else {
-                input_status = current_state_ref.input(input).unwrap();
+            StepResult::Prompt(output) | StepResult::Transitioned(output) => {
+                println!("{:?}", output);
             }
         }
 
-        if input_status.state_changed {
-            if let Some(state) = input_status.state {
-                current_state = state;
-            }
-            if input_status.submit {
-                println!(
-                    "submitting on input from state {}\n",
-                    current_state.read().get_name()
-                );
-                let collections = current_state.write().collect().unwrap().unwrap();
-                println!("{:?}", collections);
-                break;
-            }
+        let mut input = String::new();
+        std::io::stdin()
+            .read_line(&mut input)
+            .expect("error: unable to read user input");
+        if let Some('\n') = input.chars().next_back() {
+            input.pop();
+        }
+        if let Some('\r') = input.chars().next_back() {
+            input.pop();
         }
+        step_result = session.step(input).unwrap();
     }
 }
 
 fn main() {
-    let how_to_get_string_local = |name: String| {
-        let mut file = File::open(&name).unwrap();
-        let mut contents = String::new();
-        file.read_to_string(&mut contents).unwrap();
-        Ok(contents)
+    //paths are relative to the file they are written in and can not leave ../states
+    let local = FileSource::new("../states");
+
+    //sources can capture what they need
+    let delay = Duration::from_secs(3);
+    let from_api = move |path: &str| -> Result<String, LoadError> {
+        //sleep to simulate api call
+        std::thread::sleep(delay);
+        Ok(fs::read_to_string(path)?)
     };
 
-    let how_to_get_string_from_api = |name: String| {
-        //sleep 3 seconds to simulate api call
-        std::thread::sleep(std::time::Duration::from_secs(3));
+    let mut sources = Sources::new();
+    sources.add("local", local);
+    sources.add("api", from_api);
+    sources.set_default("local");
 
-        let mut file = File::open(&name).unwrap();
-        let mut contents = String::new();
-        file.read_to_string(&mut contents).unwrap();
-        Ok(contents)
-    };
+    //`cargo run -- schema > flow.schema.json` gives editors the format of the flows
+    if std::env::args().nth(1).as_deref() == Some("schema") {
+        println!("{}", schema::schema_json().unwrap());
+        return;
+    }
 
-    let functions: Vec<fn(String) -> Result<String, Box<dyn StdError>>> =
-        vec![how_to_get_string_local, how_to_get_string_from_api];
+    let mut actions = Actions::new();
+    actions.add("remember", remember);
 
-    let state =
-        SerDeState::create_from_yaml_str(functions, String::from("../states/state.yaml"), 0)
-            .unwrap()
-            .unwrap();
-    run(state.clone());
+    //`cargo run -- validate` checks the flow without running it, e.g. in CI
+    if std::env::args().nth(1).as_deref() == Some("validate") {
+        let diagnostics =
+            SerDeState::validate_with_actions(&sources, &actions, "state.yaml", None);
+        for diagnostic in &diagnostics {
+            println!("{}", diagnostic);
+        }
+        if diagnostics.iter().any(|x| x.is_error()) {
+            std::process::exit(1);
+        }
+        println!("state.yaml is valid");
+        return;
+    }
 
-    //We are using circular (STRONG ARC) references here, so we need to manually drop the state
-    state.write().destroy(true);
-}
\ No newline at end of file
+    let mut definition =
+        SerDeState::create_from_yaml_str(sources, String::from("state.yaml"), None).unwrap();
+    definition.set_actions(actions).unwrap();
+    run(Arc::new(definition));
+}
@@ -4,15 +4,43 @@ use poem::{
     listener::TcpListener,
     web::{
         websocket::{Message, WebSocket},
-        Html, Path,
+        Data, Html, Path,
     },
-    IntoResponse, Route, Server,
+    EndpointExt, IntoResponse, Route, Server,
 };
 use statemachine::{
+    action::Actions,
+    collection::Collection,
+    definition::Definition,
+    observer::Observer,
     serde_state::*,
-    status::{InputStatus, OutputStatus},
+    session::{Session, StepResult},
+    source::{LoadError, Sources},
+    value::Value,
 };
-use std::{error::Error as StdError, fs::File, io::Read};
+use std::sync::Arc;
+
+//logs the answers of every user, whatever connection they came from
+struct Logger;
+
+impl Observer for Logger {
+    fn on_submit(&self, _: &Session, collections: &[Collection]) {
+        println!("{:?}", collections);
+    }
+}
+
+//the action state.yaml names on Child31. dates are written as yyyy-mm-dd,
+//so comparing them as text compares them as dates
+fn remember(collections: &[Collection]) -> Result<(), String> {
+    let too_old = collections
+        .iter()
+        .flat_map(|x| &x.context_collections)
+        .any(|x| matches!(&x.value, Value::Date(date) if date.to_string().as_str() < "2000-01-01"));
+    if too_old {
+        return Err(String::from("Nothing before 2000 is remembered"));
+    }
+    Ok(())
+}
 
 #[handler]
 fn index() -> Html<&'static str> {
@@ -63,77 +91,50 @@ fn index() -> Html<&'static str> {
 }
 
 #[handler]
-fn ws(Path(name): Path<String>, ws: WebSocket) -> impl IntoResponse {
+fn ws(
+    Path(name): Path<String>,
+    ws: WebSocket,
+    definition: Data<&Arc<Definition>>,
+) -> impl IntoResponse {
     println!("{} connected", name);
     let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
     //user is connected
-    //create the welcome state
-    let how_to_get_string_local = |name: String| {
-        let mut file = File::open(&name).unwrap();
-        let mut contents = String::new();
-        file.read_to_string(&mut contents).unwrap();
-        Ok(contents)
-    };
-    let functions: Vec<fn(String) -> Result<String, Box<dyn StdError>>> =
-        vec![how_to_get_string_local];
-
-    let state =
-        SerDeState::create_from_yaml_str(functions, String::from("../states/state.yaml"), 0)
-            .unwrap()
-            .unwrap();
-
-    let state_drop = state.clone();
+    //every user gets their own session of the same definition
+    let definition = definition.clone();
 
     ws.on_upgrade(move |socket| async move {
         let (mut sink, mut stream) = socket.split();
         tokio::spawn(async move {
-            let mut current_state = state.clone();
+            let mut session = Session::new(definition);
+            let mut step_result = session.prompt_async().await.unwrap();
             loop {
-                let output_status: OutputStatus;
-                let input_status: InputStatus;
-                {
-                    let mut current_state_g = current_state.write();
-                    output_status = current_state_g.output().unwrap();
-                }
-                if output_status.state_changed {
-                    if let Some(state) = output_status.state {
-                        current_state = state;
-                        continue;
-                    }
-                    if output_status.submit {
-                        let collections = current_state.write().collect().unwrap().unwrap();
-                        println!("{:?}", collections);
+                let output = match step_result {
+                    StepResult::Submitted(_) => {
                         let _ = sender.send(format!("Thank you for your input!"));
                         break;
                     }
-                }
+                    StepResult::Rejected(output, reason) => format!(
+                        "Input not recognized: {}\n{:?}",
+                        reason.unwrap_or_default(),
+                        output
+                    ),
+                    StepResult::Prompt(output) | StepResult::Transitioned(output) => {
+                        format!("{:?}", output)
+                    }
+                };
 
-                if sender.send(format!("{:?}", output_status.output)).is_err() {
+                if sender.send(output).is_err() {
                     break;
                 }
 
-                if let Some(Ok(msg)) = stream.next().await {
-                    if let Message::Text(input) = msg {
-                        {
-                            let mut current_state_g = current_state.write();
-                            if input == "back" {
-                                input_status = current_state_g.back();
-                            } else {
-                                input_status = current_state_g.input(input).unwrap();
-                            }
-                        }
-                        if input_status.state_changed {
-                            if let Some(state) = input_status.state {
-                                current_state = state;
-                            }
-                            if input_status.submit {
-                                let collections = current_state.write().collect().unwrap().unwrap();
-                                println!("{:?}", collections);
-                                let _ = sender.send(format!("Thank you for your input!"));
-                                break;
-                            }
-                        }
+                match stream.next().await {
+                    Some(Ok(Message::Text(input))) => {
+                        step_result = session.step_async(input).await.unwrap();
                     }
+                    Some(Ok(_)) => {
+                        step_result = session.prompt_async().await.unwrap();
+                    }
+                    _ => break,
                 }
             }
         });
@@ -144,8 +145,6 @@ fn ws(Path(name): Path<String>, ws: WebSocket) -> impl IntoResponse {
                     break;
                 }
             }
-            //We are using circular (STRONG ARC) references here, so we need to manually drop the state
-            state_drop.write().destroy(true);
         });
     })
 }
@@ -154,7 +153,37 @@ fn ws(Path(name): Path<String>, ws: WebSocket) -> impl IntoResponse {
 async fn main() -> Result<(), std::io::Error> {
     tracing_subscriber::fmt::init();
 
-    let app = Route::new().at("/", get(index)).at("/ws/:name", get(ws));
+    //files are read without blocking the executor
+    let local = |path: String| async move {
+        let string = tokio::fs::read_to_string(format!("../states/{}", path)).await?;
+        Ok::<_, LoadError>(string)
+    };
+    //state3.yaml loads state2.yaml through the api source, the path is given as it is
+    let from_api = |path: String| async move {
+        //sleep to simulate api call
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        let string = tokio::fs::read_to_string(path).await?;
+        Ok::<_, LoadError>(string)
+    };
+    let mut sources = Sources::new();
+    sources.add_async("local", local);
+    sources.add_async("api", from_api);
+    sources.set_default("local");
+
+    //the flow is parsed once and shared by all users
+    let mut definition =
+        SerDeState::create_from_yaml_str_async(sources, String::from("state.yaml"), None)
+            .await
+            .unwrap();
+    definition.add_observer(Logger);
+    let mut actions = Actions::new();
+    actions.add("remember", remember);
+    definition.set_actions(actions).unwrap();
+
+    let app = Route::new()
+        .at("/", get(index))
+        .at("/ws/:name", get(ws))
+        .data(Arc::new(definition));
 
     Server::new(TcpListener::bind("127.0.0.1:3000"))
         .run(app)
@@ -0,0 +1,44 @@
+use crate::collection::Collection;
+use std::{collections::HashMap, sync::Arc};
+
+// a handler that options and context states name in the definition, e.g. action: create_ticket.
+// it gets the answers collected up to the state and gives the reason if it failed.
+// a failed action goes to the failure state if there is one, otherwise the input is rejected.
+// closures are actions too
+pub trait Action: Send + Sync {
+    fn run(&self, collections: &[Collection]) -> Result<(), String>;
+}
+
+impl<F> Action for F
+where
+    F: Fn(&[Collection]) -> Result<(), String> + Send + Sync,
+{
+    fn run(&self, collections: &[Collection]) -> Result<(), String> {
+        self(collections)
+    }
+}
+
+// the actions a definition can name, by their name
+#[derive(Clone, Default)]
+pub struct Actions {
+    actions: HashMap<String, Arc<dyn Action>>,
+}
+
+impl Actions {
+    pub fn new() -> Actions {
+        Actions::default()
+    }
+
+    // adds an action, replacing the one with the same name
+    pub fn add(&mut self, name: impl Into<String>, action: impl Action + 'static) {
+        self.actions.insert(name.into(), Arc::new(action));
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Action>> {
+        self.actions.get(name).cloned()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.actions.contains_key(name)
+    }
+}
@@ -1,4 +1,6 @@
-#[derive(Debug)]
+use crate::value::Value;
+
+#[derive(Debug, Clone)]
 pub struct Collection {
     pub state_name: String,
     pub context_collections: Vec<ContextLikeCollection>,
@@ -12,14 +14,14 @@ impl Collection {
         }
     }
 }
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ContextLikeCollection {
     pub name: String,
-    pub value: String,
+    pub value: Value,
 }
 
 impl ContextLikeCollection {
-    pub fn new(name: String, value: String) -> ContextLikeCollection {
+    pub fn new(name: String, value: Value) -> ContextLikeCollection {
         ContextLikeCollection { name, value }
     }
 }
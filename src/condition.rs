@@ -0,0 +1,55 @@
+use crate::{expression::Expression, value::Value};
+use serde::{Deserialize, Serialize};
+
+// decides if an option, a context or a next state is there, by what the user answered before.
+// answers are found by the name of their context or of their OptionsState
+// and compared by how they are written, e.g. yes/no for booleans
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Condition {
+    Answered(String /*name*/),
+    Equals(String /*name*/, String /*value*/),
+    NotEquals(String /*name*/, String /*value*/),
+    OneOf(String /*name*/, Vec<String> /*values*/),
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    // answer gives the value the user gave for a name, if they gave one
+    pub fn evaluate(&self, answer: &impl Fn(&str) -> Option<Value>) -> bool {
+        match self {
+            Condition::Answered(name) => answer(name).is_some(),
+            Condition::Equals(name, value) => answer(name)
+                .map(|x| x.to_string() == *value)
+                .unwrap_or(false),
+            Condition::NotEquals(name, value) => answer(name)
+                .map(|x| x.to_string() != *value)
+                .unwrap_or(true),
+            Condition::OneOf(name, values) => answer(name)
+                .map(|x| values.contains(&x.to_string()))
+                .unwrap_or(false),
+            Condition::All(conditions) => conditions.iter().all(|x| x.evaluate(answer)),
+            Condition::Any(conditions) => conditions.iter().any(|x| x.evaluate(answer)),
+            Condition::Not(condition) => !condition.evaluate(answer),
+        }
+    }
+}
+
+// what decides if an option, a context or a next state is there.
+// in yaml a guard is either a Condition or the text of an Expression
+#[derive(Debug, Clone)]
+pub enum Guard {
+    Condition(Condition),
+    Expression(Expression),
+}
+
+impl Guard {
+    pub fn evaluate(&self, answer: &impl Fn(&str) -> Option<Value>) -> bool {
+        match self {
+            Guard::Condition(condition) => condition.evaluate(answer),
+            Guard::Expression(expression) => expression.evaluate(answer),
+        }
+    }
+}
@@ -1,6 +1,10 @@
-use crate::{collection::ContextLikeCollection, error::Error as StateError, state::State};
-use parking_lot::RwLock;
-use std::{error::Error as StdError, fmt::Display, sync::Arc};
+use crate::{
+    condition::Guard,
+    machine::StateId,
+    validation::{self, Rule},
+    value::{Kind, Value},
+};
+use std::fmt::Display;
 
 pub enum Context {
     StateContext(StateContext),
@@ -8,16 +12,8 @@ pub enum Context {
 }
 
 impl Context {
-    pub fn input(&mut self, input: String) {
-        match self {
-            Context::StateContext(state_context) => state_context.input(input),
-            Context::StateOptionsContext(state_options_context) => {
-                state_options_context.input(input)
-            }
-        }
-    }
-
-    pub fn output(&mut self) -> Result<Option<Arc<RwLock<State>>>, Box<dyn StdError>> {
+    // the state to go to when this context is reached
+    pub fn output(&self) -> Option<StateId> {
         match self {
             Context::StateContext(state_context) => state_context.output(),
             Context::StateOptionsContext(state_options_context) => state_options_context.output(),
@@ -31,28 +27,31 @@ impl Context {
         }
     }
 
-    pub fn get_value(&self) -> String {
+    // the context is only asked if the condition holds
+    pub fn get_when(&self) -> Option<&Guard> {
         match self {
-            Context::StateContext(state_context) => state_context.get_value(),
+            Context::StateContext(state_context) => state_context.when.as_ref(),
             Context::StateOptionsContext(state_options_context) => {
-                state_options_context.get_value()
+                state_options_context.when.as_ref()
             }
         }
     }
 
-    pub fn collect(
-        &mut self,
-    ) -> Result<Result<ContextLikeCollection, StateError>, Box<dyn StdError>> {
+    // gives the reason if the input can not be the value of this context
+    pub fn validate(&self, input: &str) -> Option<String> {
         match self {
-            Context::StateContext(state_context) => state_context.collect(),
-            Context::StateOptionsContext(state_options_context) => state_options_context.collect(),
+            Context::StateContext(state_context) => state_context.validate(input),
+            Context::StateOptionsContext(_) => None,
         }
     }
 
-    pub fn destroy(&mut self) {
+    // the value given in the definition, used until the user enters one
+    pub fn get_value(&self) -> String {
         match self {
-            Context::StateContext(state_context) => state_context.destroy(),
-            Context::StateOptionsContext(state_options_context) => state_options_context.destroy(),
+            Context::StateContext(state_context) => state_context.get_value(),
+            Context::StateOptionsContext(state_options_context) => {
+                state_options_context.get_value()
+            }
         }
     }
 }
@@ -61,19 +60,45 @@ impl Context {
 pub struct StateContext {
     pub name: String,
     pub value: String,
+    pub kind: Kind,
+    pub rules: Vec<Rule>,
+    pub when: Option<Guard>,
 }
 
 impl StateContext {
-    pub fn new(name: String, value: String) -> StateContext {
-        StateContext { name, value }
+    pub fn new(
+        name: String,
+        value: String,
+        kind: Kind,
+        rules: Vec<Rule>,
+        when: Option<Guard>,
+    ) -> StateContext {
+        StateContext {
+            name,
+            value,
+            kind,
+            rules,
+            when,
+        }
     }
 
-    fn input(&mut self, input: String) {
-        self.value = input;
+    fn validate(&self, input: &str) -> Option<String> {
+        if let Err(reason) = self.kind.parse(input) {
+            return Some(reason);
+        }
+        validation::check_all(&self.rules, input)
+    }
+
+    // the typed value of what has been entered. entered values have been validated,
+    // anything else is kept as text
+    pub fn parse(&self, value: &str) -> Value {
+        self.kind
+            .parse(value)
+            .unwrap_or_else(|_| Value::Text(value.to_string()))
     }
 
-    fn output(&mut self) -> Result<Option<Arc<RwLock<State>>>, Box<dyn StdError>> {
-        Ok(None)
+    fn output(&self) -> Option<StateId> {
+        None
     }
 
     fn get_name(&self) -> String {
@@ -83,15 +108,6 @@ impl StateContext {
     fn get_value(&self) -> String {
         self.value.clone()
     }
-
-    fn collect(&mut self) -> Result<Result<ContextLikeCollection, StateError>, Box<dyn StdError>> {
-        Ok(Ok(ContextLikeCollection::new(
-            self.name.clone(),
-            self.value.clone(),
-        )))
-    }
-
-    fn destroy(&mut self) {}
 }
 
 #[derive(Clone)]
@@ -103,29 +119,27 @@ pub struct StateOptionsContext {
     //the next state of the ContextState would be the parent of the state that has this option
     pub name: String,
     pub value: String,
-    pub state: Option<Arc<RwLock<State>>>,
+    pub state: StateId,
+    pub when: Option<Guard>,
 }
 
 impl StateOptionsContext {
-    pub fn new(name: String, value: String, state: Arc<RwLock<State>>) -> StateOptionsContext {
+    pub fn new(
+        name: String,
+        value: String,
+        state: StateId,
+        when: Option<Guard>,
+    ) -> StateOptionsContext {
         StateOptionsContext {
             name,
             value,
-            state: Some(state),
+            state,
+            when,
         }
     }
 
-    fn input(&mut self, input: String) {
-        self.value = input;
-    }
-
-    fn output(&mut self) -> Result<Option<Arc<RwLock<State>>>, Box<dyn StdError>> {
-        let s = self.state.as_ref().unwrap().write().into_state_sandwich()?;
-        if s.is_some() {
-            Ok(s)
-        } else {
-            Ok(Some(self.state.as_ref().unwrap().clone()))
-        }
+    fn output(&self) -> Option<StateId> {
+        Some(self.state)
     }
 
     fn get_name(&self) -> String {
@@ -135,52 +149,6 @@ impl StateOptionsContext {
     fn get_value(&self) -> String {
         self.value.clone()
     }
-
-    fn collect(&mut self) -> Result<Result<ContextLikeCollection, StateError>, Box<dyn StdError>> {
-        let s = self.state.as_ref().unwrap().write().into_state_sandwich()?;
-        let state = if s.is_some() {
-            s.unwrap().clone()
-        } else {
-            self.state.as_ref().unwrap().clone()
-        };
-        let mut state = state.write();
-        let index = state.get_index();
-        let options = state.get_options();
-        if let Some(options) = options {
-            let len = options.len();
-            if let Some(option) = options.get_mut(index) {
-                if index == len - 1 {
-                    let in_state = option.get_state()?;
-                    if let Some(in_state) = in_state {
-                        let mut in_state = in_state.write();
-                        let contexts = in_state.get_contexts();
-                        if let Some(contexts) = contexts {
-                            let context = contexts.get_mut(0);
-                            if let Some(context) = context {
-                                return Ok(Ok(ContextLikeCollection::new(
-                                    self.name.clone(),
-                                    context.get_value(),
-                                )));
-                            }
-                        }
-                    }
-                }
-                return Ok(Ok(ContextLikeCollection::new(
-                    self.name.clone(),
-                    option.get_name(),
-                )));
-            }
-        }
-        //something went wrong
-        Ok(Err(StateError::BadConstruction))
-    }
-
-    fn destroy(&mut self) {
-        if let Some(state) = &self.state {
-            state.write().destroy(false);
-        }
-        self.state = None;
-    }
 }
 
 impl Display for Context {
@@ -208,10 +176,8 @@ impl Display for StateOptionsContext {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "StateOptionsContext: Name: {}, Value: {} | Parent: {}",
-            self.name,
-            self.value,
-            self.state.as_ref().unwrap().read().get_name()
+            "StateOptionsContext: Name: {}, Value: {} | State: {}",
+            self.name, self.value, self.state
         )
     }
 }
@@ -0,0 +1,132 @@
+use crate::{
+    action::Actions,
+    error::Error,
+    machine::{Machine, StateId},
+    observer::Observer,
+    trace::event,
+};
+use parking_lot::RwLock;
+use std::{collections::HashMap, sync::Arc};
+
+// a flow that has been built once and can be shared by any number of sessions.
+// sessions keep their own snapshot of the machine, so reading it needs no lock.
+// when a session reaches a state that has not been loaded yet, the state is loaded
+// into a copy of the machine that replaces the shared one. states are only ever
+// appended, so the StateIds of older snapshots stay valid
+pub struct Definition {
+    machine: RwLock<Arc<Machine>>,
+    // told about every session of the definition, see Session::add_observer
+    observers: Vec<Arc<dyn Observer>>,
+    // the actions the options and context states name
+    actions: Actions,
+}
+
+impl Definition {
+    pub fn new(mut machine: Machine) -> Definition {
+        //the strings of the paths are only needed while they are loaded
+        machine.take_fetched();
+        Definition {
+            machine: RwLock::new(Arc::new(machine)),
+            observers: vec![],
+            actions: Actions::new(),
+        }
+    }
+
+    pub fn get_machine(&self) -> Arc<Machine> {
+        self.machine.read().clone()
+    }
+
+    pub fn add_observer(&mut self, observer: impl Observer + 'static) {
+        self.observers.push(Arc::new(observer));
+    }
+
+    pub fn get_observers(&self) -> &[Arc<dyn Observer>] {
+        &self.observers
+    }
+
+    // fails if a state that has been loaded names an action that is not there.
+    // states of lazy paths are checked once they are loaded, see also
+    // SerDeState::validate_with_actions that checks every path without running a session
+    pub fn set_actions(&mut self, actions: Actions) -> Result<(), Error> {
+        self.get_machine().check_actions(&actions, 0)?;
+        self.actions = actions;
+        Ok(())
+    }
+
+    pub fn get_actions(&self) -> &Actions {
+        &self.actions
+    }
+
+    // loads the state a StateHolder stands for and gives the machine that contains it
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self, id), fields(state = %id)))]
+    pub fn load(&self, id: StateId) -> Result<Arc<Machine>, Error> {
+        let mut fetched = HashMap::new();
+        loop {
+            if let Some(machine) = self.try_load(id, &mut fetched)? {
+                return Ok(machine);
+            }
+        }
+    }
+
+    // loads like load, but paths from async sources are awaited.
+    // the state is built once every path it needs has been fetched
+    #[cfg(feature = "async")]
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self, id), fields(state = %id)))]
+    pub async fn load_async(&self, id: StateId) -> Result<Arc<Machine>, Error> {
+        let mut fetched = HashMap::new();
+        loop {
+            let (source, path) = match self.try_load(id, &mut fetched) {
+                Ok(Some(machine)) => return Ok(machine),
+                Ok(None) => continue,
+                Err(Error::NeedsAsync { source, path }) => (source, path),
+                Err(error) => return Err(error),
+            };
+            let sources = self.get_machine().get_sources().clone();
+            event!(DEBUG, path = %path, "fetching from an async source");
+            let string = sources.load_async(source.as_deref(), &path).await?;
+            let source = sources.get_name(source.as_deref())?.to_string();
+            fetched.insert((source, path), string);
+        }
+    }
+
+    // loads the state into a copy of the shared machine without holding the lock,
+    // so other sessions are not held up while the paths are read.
+    // gives None if another load replaced the shared machine in the meantime,
+    // the strings read so far are kept in fetched so the next try does not read them again
+    fn try_load(
+        &self,
+        id: StateId,
+        fetched: &mut HashMap<(String, String), String>,
+    ) -> Result<Option<Arc<Machine>>, Error> {
+        #[cfg(feature = "tracing")]
+        let started = std::time::Instant::now();
+        let shared = self.get_machine();
+        if !shared.contains(id) {
+            return Err(Error::Misuse(format!("State {} is not in the machine", id)));
+        }
+        //another session might have loaded it already
+        if shared.get_loaded(id).is_some() {
+            return Ok(Some(shared));
+        }
+
+        let mut machine = Machine::clone(&shared);
+        machine.set_fetched(std::mem::take(fetched));
+        let resolved = machine.resolve(id);
+        *fetched = machine.take_fetched();
+        resolved?;
+        machine.check_actions(&self.actions, shared.len())?;
+
+        let mut current = self.machine.write();
+        if !Arc::ptr_eq(&current, &shared) {
+            return Ok(None);
+        }
+        *current = Arc::new(machine);
+        event!(
+            INFO,
+            path = %current.get_name(id),
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "lazy path loaded"
+        );
+        Ok(Some(current.clone()))
+    }
+}
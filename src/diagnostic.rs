@@ -0,0 +1,666 @@
+use crate::{
+    action::Actions,
+    error::Error,
+    machine::Machine,
+    serde_state::{
+        ContextType, SerDeContextState, SerDeIntoState, SerDeOption, SerDeOptionsContext,
+        SerDePath, SerDeState, StateType,
+    },
+    source::Sources,
+};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    // the flow can not be built or can not be finished
+    Error,
+    // the flow works, but probably not the way it was meant to
+    Warning,
+}
+
+// something wrong with a definition, found without running it
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    // the path of the document, None if it is about the whole flow
+    pub path: Option<String>,
+    // the name of the state, None if it is about a whole document
+    pub state: Option<String>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "error")?,
+            Severity::Warning => write!(f, "warning")?,
+        }
+        if let Some(path) = &self.path {
+            write!(f, " in {}", path)?;
+        }
+        if let Some(state) = &self.state {
+            write!(f, ", state {}", state)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+// checks the root and every path it leads to, lazy or not, without running any session.
+// if nothing is wrong with their structure, the definition is also built with all its paths
+// loaded, so errors that only building finds (e.g. in guards or rules) are given too.
+// every document is loaded once, building takes the ones that were checked.
+// documents of async sources can only be checked with validate_async
+pub fn validate(sources: &Sources, name: &str, source: Option<&str>) -> Vec<Diagnostic> {
+    check(sources, None, name, source)
+}
+
+// validates like validate and also gives an error for every action that is not one of the actions
+pub fn validate_with_actions(
+    sources: &Sources,
+    actions: &Actions,
+    name: &str,
+    source: Option<&str>,
+) -> Vec<Diagnostic> {
+    check(sources, Some(actions), name, source)
+}
+
+// validates like validate, but documents from async sources are awaited
+#[cfg(feature = "async")]
+pub async fn validate_async(
+    sources: &Sources,
+    name: &str,
+    source: Option<&str>,
+) -> Vec<Diagnostic> {
+    check_async(sources, None, name, source).await
+}
+
+// validates like validate_with_actions, but documents from async sources are awaited
+#[cfg(feature = "async")]
+pub async fn validate_with_actions_async(
+    sources: &Sources,
+    actions: &Actions,
+    name: &str,
+    source: Option<&str>,
+) -> Vec<Diagnostic> {
+    check_async(sources, Some(actions), name, source).await
+}
+
+fn check(
+    sources: &Sources,
+    actions: Option<&Actions>,
+    name: &str,
+    source: Option<&str>,
+) -> Vec<Diagnostic> {
+    let mut validator = Validator::new(sources, actions);
+    let root = validator.root(name, source);
+    while let Some((source, path)) = validator.pending.pop_front() {
+        let loaded = sources.load(Some(&source), &path);
+        validator.document(source, path, loaded);
+    }
+    validator.finish(root)
+}
+
+#[cfg(feature = "async")]
+async fn check_async(
+    sources: &Sources,
+    actions: Option<&Actions>,
+    name: &str,
+    source: Option<&str>,
+) -> Vec<Diagnostic> {
+    let mut validator = Validator::new(sources, actions);
+    let root = validator.root(name, source);
+    while let Some((source, path)) = validator.pending.pop_front() {
+        let loaded = sources.load_async(Some(&source), &path).await;
+        validator.document(source, path, loaded);
+    }
+    validator.finish(root)
+}
+
+// the source and path of a document
+type Location = (String, String);
+
+struct Validator<'a> {
+    sources: &'a Sources,
+    // the actions the states can name, nothing if they are not checked
+    actions: Option<&'a Actions>,
+    diagnostics: Vec<Diagnostic>,
+    // the documents that are still to be checked, by their source and path
+    pending: VecDeque<Location>,
+    seen: HashSet<Location>,
+    // the document that is being checked
+    location: Option<Location>,
+    // false while walking states that are never entered, e.g. a failure state without an action
+    reachable: bool,
+    // whether a state of the document that is being checked submits and can be reached
+    // from its root
+    submits: bool,
+    // the documents the document that is being checked can go to
+    leads_to: Vec<Location>,
+    // what was found for every document that was checked, see submits and leads_to
+    documents: HashMap<Location, (bool, Vec<Location>)>,
+    // the strings of the documents that were checked, building takes them instead of
+    // loading the documents again
+    fetched: HashMap<Location, String>,
+}
+
+impl<'a> Validator<'a> {
+    fn new(sources: &'a Sources, actions: Option<&'a Actions>) -> Validator<'a> {
+        Validator {
+            sources,
+            actions,
+            diagnostics: vec![],
+            pending: VecDeque::new(),
+            seen: HashSet::new(),
+            location: None,
+            reachable: true,
+            submits: false,
+            leads_to: vec![],
+            documents: HashMap::new(),
+            fetched: HashMap::new(),
+        }
+    }
+
+    // gives the location of the root and adds it to the documents that are to be checked
+    fn root(&mut self, name: &str, source: Option<&str>) -> Result<Location, Error> {
+        let sources = self.sources;
+        let root = sources
+            .get_name(source)
+            .and_then(|source| Ok((source.to_string(), sources.join(Some(source), None, name)?)));
+        match &root {
+            Ok(root) => {
+                self.seen.insert(root.clone());
+                self.pending.push_back(root.clone());
+            }
+            Err(error) => self.error(Some(name.to_string()), None, error.to_string()),
+        }
+        root
+    }
+
+    // once every document has been checked, checks that the flow can submit and builds it
+    fn finish(mut self, root: Result<Location, Error>) -> Vec<Diagnostic> {
+        let root = match root {
+            Ok(root) => root,
+            Err(_) => return self.diagnostics,
+        };
+        //a document that could not be checked might have submitted
+        if !self.submits_from(&root) && !self.diagnostics.iter().any(|x| x.is_error()) {
+            self.error(
+                None,
+                None,
+                String::from("No state that can be reached submits the flow"),
+            );
+        }
+        if self.diagnostics.iter().any(|x| x.is_error()) {
+            return self.diagnostics;
+        }
+
+        if let Err(error) = self.build(&root) {
+            let (path, state) = match &error {
+                Error::Load { path, .. } | Error::Parse { path, .. } => (Some(path.clone()), None),
+                Error::InvalidDefinition { state, .. } => (None, Some(state.clone())),
+                _ => (None, None),
+            };
+            self.diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                path,
+                state,
+                message: format!("The definition could not be built: {}", error),
+            });
+        }
+        self.diagnostics
+    }
+
+    // builds the definition with every path loaded from the documents that were checked
+    fn build(&mut self, root: &Location) -> Result<(), Error> {
+        let (source, path) = root;
+        let fetched = std::mem::take(&mut self.fetched);
+        let state = match fetched.get(root) {
+            Some(string) => self.sources.parse(Some(source), path, string)?,
+            None => self.sources.load_state(Some(source), path)?,
+        };
+        let mut machine = Machine::new(self.sources.clone());
+        machine.set_root_location(Some(source), path)?;
+        machine.set_fetched(fetched);
+        let root = state.into_state(&mut machine, None)?;
+        machine.set_root(root);
+        machine.resolve_all()
+    }
+
+    fn error(&mut self, path: Option<String>, state: Option<&str>, message: String) {
+        self.push(Severity::Error, path, state, message);
+    }
+
+    fn push(
+        &mut self,
+        severity: Severity,
+        path: Option<String>,
+        state: Option<&str>,
+        message: String,
+    ) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            path,
+            state: state.map(String::from),
+            message,
+        });
+    }
+
+    // adds a diagnostic about a state of the document that is being checked
+    fn state(&mut self, severity: Severity, state: &SerDeState, message: String) {
+        let path = self.location.as_ref().map(|(_, path)| path.clone());
+        self.push(severity, path, Some(&state.name), message);
+    }
+
+    // checks a document with what was loaded for it
+    fn document(&mut self, source: String, path: String, loaded: Result<String, Error>) {
+        let state = match loaded.and_then(|string| {
+            let state = self.sources.parse(Some(&source), &path, &string)?;
+            self.fetched.insert((source.clone(), path.clone()), string);
+            Ok(state)
+        }) {
+            Ok(state) => state,
+            Err(error) => return self.error(Some(path), None, error.to_string()),
+        };
+        self.location = Some((source.clone(), path.clone()));
+        self.reachable = true;
+        self.submits = false;
+        self.walk_state(&state);
+        let leads_to = std::mem::take(&mut self.leads_to);
+        self.documents
+            .insert((source, path), (self.submits, leads_to));
+    }
+
+    // whether a submit can be reached from the root of the document,
+    // following the paths the documents can go to
+    fn submits_from(&self, root: &Location) -> bool {
+        let mut seen = HashSet::from([root]);
+        let mut pending = vec![root];
+        while let Some(location) = pending.pop() {
+            let (submits, leads_to) = match self.documents.get(location) {
+                Some(document) => document,
+                None => continue,
+            };
+            if *submits {
+                return true;
+            }
+            for next in leads_to {
+                if seen.insert(next) {
+                    pending.push(next);
+                }
+            }
+        }
+        false
+    }
+
+    fn submit(&mut self) {
+        if self.reachable {
+            self.submits = true;
+        }
+    }
+
+    // adds an error if the action is not one of the actions
+    fn check_action(&mut self, state: &SerDeState, action: Option<&str>) {
+        if let (Some(actions), Some(action)) = (self.actions, action) {
+            if !actions.contains(action) {
+                self.state(Severity::Error, state, format!("Unknown action {}", action));
+            }
+        }
+    }
+
+    // walks a failure state, it is only entered if there is an action that can fail
+    fn walk_failure(&mut self, state: &SerDeState, failure: &SerDeIntoState, action: bool) {
+        let reachable = self.reachable;
+        self.reachable &= action;
+        self.walk_into(state, failure);
+        self.reachable = reachable;
+    }
+
+    fn walk_state(&mut self, state: &SerDeState) {
+        match &state.r#type {
+            StateType::Options(options) => {
+                if options.is_empty() {
+                    self.state(Severity::Error, state, String::from("Has no options"));
+                }
+                self.walk_options(state, options);
+            }
+            StateType::Context(SerDeContextState {
+                contexts,
+                submit,
+                next,
+                action,
+                failure,
+            }) => {
+                //the output of a context state starts with its first context
+                if contexts.is_empty() {
+                    self.state(Severity::Error, state, String::from("Has no contexts"));
+                }
+                if *submit || can_submit(next.as_deref()) {
+                    self.submit();
+                }
+                let names: Vec<&str> = contexts.iter().map(|x| x.name.as_str()).collect();
+                for name in duplicates(&names) {
+                    let message = format!("Has more than one context named {}", name);
+                    self.state(Severity::Warning, state, message);
+                }
+                for context in contexts {
+                    if let ContextType::Options(SerDeOptionsContext { options, .. }) =
+                        &context.r#type
+                    {
+                        if options.is_empty() {
+                            let message = format!("Context {} has no options", context.name);
+                            self.state(Severity::Error, state, message);
+                        }
+                        self.walk_options(state, options);
+                    }
+                }
+                if let Some(next) = next {
+                    self.walk_into(state, next);
+                }
+                self.check_action(state, action.as_deref());
+                if let Some(failure) = failure {
+                    self.walk_failure(state, failure, action.is_some());
+                }
+                if action.is_none() && failure.is_some() {
+                    let message = String::from("Has a failure state but no action");
+                    self.state(Severity::Warning, state, message);
+                }
+            }
+        }
+    }
+
+    fn walk_options(&mut self, state: &SerDeState, options: &[SerDeOption]) {
+        let names: Vec<&str> = options.iter().map(|x| x.name.as_str()).collect();
+        for name in duplicates(&names) {
+            let message = format!("Has more than one option named {}", name);
+            self.state(Severity::Warning, state, message);
+        }
+        if !options.is_empty() && options.iter().all(|x| x.when.is_some()) {
+            let message = String::from("Every option has a condition, it might show none");
+            self.state(Severity::Warning, state, message);
+        }
+        for option in options {
+            if option.submit.unwrap_or(false) {
+                self.submit();
+            }
+            self.check_action(state, option.action.as_deref());
+            if let Some(into) = &option.state {
+                self.walk_into(state, into);
+            }
+            if let Some(failure) = &option.failure {
+                self.walk_failure(state, failure, option.action.is_some());
+            }
+            if option.action.is_none() && option.failure.is_some() {
+                let message = format!("Option {} has a failure state but no action", option.name);
+                self.state(Severity::Warning, state, message);
+            }
+        }
+    }
+
+    fn walk_into(&mut self, state: &SerDeState, into: &SerDeIntoState) {
+        match into {
+            SerDeIntoState::Inline(inline) => self.walk_state(inline),
+            SerDeIntoState::Path(path) => self.add_path(state, path),
+            SerDeIntoState::When(_, into) => self.walk_into(state, into),
+            SerDeIntoState::Branches(branches) => {
+                for branch in branches {
+                    self.walk_into(state, &branch.state);
+                }
+            }
+        }
+    }
+
+    // paths are joined the same way the machine joins them
+    fn add_path(&mut self, state: &SerDeState, path: &SerDePath) {
+        let sources = self.sources;
+        let joined = sources.get_name(path.source.as_deref()).and_then(|source| {
+            let base = match &self.location {
+                Some((base_source, base)) if base_source == source => Some(base.as_str()),
+                _ => None,
+            };
+            Ok((
+                source.to_string(),
+                sources.join(Some(source), base, &path.path)?,
+            ))
+        });
+        match joined {
+            Ok(joined) => {
+                if self.reachable {
+                    self.leads_to.push(joined.clone());
+                }
+                if self.seen.insert(joined.clone()) {
+                    self.pending.push_back(joined);
+                }
+            }
+            Err(error) => self.state(Severity::Error, state, error.to_string()),
+        }
+    }
+}
+
+// whether a context state with the next state submits once every context is answered.
+// it does if there is no next state or none of its branches might hold
+fn can_submit(next: Option<&SerDeIntoState>) -> bool {
+    match next {
+        None | Some(SerDeIntoState::When(_, _)) => true,
+        Some(SerDeIntoState::Branches(branches)) => branches.iter().all(|x| x.when.is_some()),
+        Some(_) => false,
+    }
+}
+
+// gives every name that is there more than once, once
+fn duplicates<'a>(names: &[&'a str]) -> Vec<&'a str> {
+    let mut seen = HashSet::new();
+    let mut duplicates = vec![];
+    for name in names {
+        if !seen.insert(*name) && !duplicates.contains(name) {
+            duplicates.push(*name);
+        }
+    }
+    duplicates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::LoadError;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    // the documents by their path, from the default source. counts how often they are loaded
+    fn sources(documents: &[(&'static str, &'static str)], loads: Arc<AtomicUsize>) -> Sources {
+        let documents: HashMap<&str, &str> = documents.iter().copied().collect();
+        let mut sources = Sources::new();
+        sources.add("local", move |path: &str| {
+            loads.fetch_add(1, Ordering::SeqCst);
+            match documents.get(path) {
+                Some(document) => Ok(document.to_string()),
+                None => Err(LoadError::new(format!("{} does not exist", path))),
+            }
+        });
+        sources.set_default("local");
+        sources
+    }
+
+    // the errors of a flow that starts at main.yaml
+    fn errors_of(documents: &[(&'static str, &'static str)]) -> Vec<Diagnostic> {
+        let sources = sources(documents, Arc::default());
+        validate(&sources, "main.yaml", None)
+            .into_iter()
+            .filter(|x| x.is_error())
+            .collect()
+    }
+
+    fn has(diagnostics: &[Diagnostic], state: &str, message: &str) -> bool {
+        diagnostics
+            .iter()
+            .any(|x| x.state.as_deref() == Some(state) && x.message.contains(message))
+    }
+
+    const SUBMIT: &str = r#"
+version: 2
+name: Submit
+description: d
+type: !Options
+- name: Yes
+  submit: true
+"#;
+
+    #[test]
+    fn empty_options() {
+        let errors = errors_of(&[
+            (
+                "main.yaml",
+                r#"
+version: 2
+name: Main
+description: d
+type: !Context
+  contexts:
+  - name: Where?
+    type: !Options
+      options: []
+      other_label: Else where
+      other_question: Where could it be?
+  next: !Path submit.yaml
+"#,
+            ),
+            ("submit.yaml", SUBMIT),
+        ]);
+        assert!(has(&errors, "Main", "Context Where? has no options"));
+
+        let errors = errors_of(&[(
+            "main.yaml",
+            "version: 2\nname: Main\ndescription: d\ntype: !Options []\n",
+        )]);
+        assert!(has(&errors, "Main", "Has no options"));
+    }
+
+    #[test]
+    fn empty_contexts() {
+        let errors = errors_of(&[(
+            "main.yaml",
+            r#"
+version: 2
+name: Main
+description: d
+type: !Context
+  contexts: []
+  submit: true
+"#,
+        )]);
+        assert!(has(&errors, "Main", "Has no contexts"));
+    }
+
+    #[test]
+    fn unknown_source() {
+        let errors = errors_of(&[(
+            "main.yaml",
+            r#"
+version: 2
+name: Main
+description: d
+type: !Options
+- name: Go
+  state: !Path
+    path: submit.yaml
+    source: api
+- name: Done
+  submit: true
+"#,
+        )]);
+        assert!(has(&errors, "Main", "Unknown source api"));
+    }
+
+    #[test]
+    fn no_reachable_submit() {
+        //the only submit is in a failure state that is never entered, there is no action
+        let errors = errors_of(&[
+            (
+                "main.yaml",
+                r#"
+version: 2
+name: Main
+description: d
+type: !Context
+  contexts:
+  - name: q
+    type: !Normal
+  failure: !Path submit.yaml
+  next: !Path main.yaml
+"#,
+            ),
+            ("submit.yaml", SUBMIT),
+        ]);
+        assert!(errors
+            .iter()
+            .any(|x| x.message == "No state that can be reached submits the flow"));
+    }
+
+    #[test]
+    fn every_document_is_loaded_once() {
+        let loads = Arc::new(AtomicUsize::new(0));
+        let sources = sources(
+            &[
+                (
+                    "main.yaml",
+                    r#"
+version: 2
+name: Main
+description: d
+type: !Options
+- name: Go
+  state: !Path submit.yaml
+- name: Again
+  state: !Path
+    path: main.yaml
+    lazy: true
+"#,
+                ),
+                ("submit.yaml", SUBMIT),
+            ],
+            loads.clone(),
+        );
+        assert!(validate(&sources, "main.yaml", None).is_empty());
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+    }
+
+    // runs a future whose sources never have to wait
+    #[cfg(feature = "async")]
+    fn ready<F: std::future::Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+        loop {
+            if let std::task::Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_sources() {
+        let mut sources = Sources::new();
+        sources.add_async("api", |path: String| async move {
+            match path.as_str() {
+                "main.yaml" => Ok(String::from(SUBMIT)),
+                _ => Err(LoadError::new(format!("{} does not exist", path))),
+            }
+        });
+        sources.set_default("api");
+        //a sync validation can not load them
+        assert!(validate(&sources, "main.yaml", None)
+            .iter()
+            .any(|x| x.is_error()));
+        assert!(ready(validate_async(&sources, "main.yaml", None)).is_empty());
+    }
+}
@@ -1,22 +1,120 @@
+use crate::source::LoadError;
 use std::{error::Error as StdError, fmt};
 
 #[derive(Debug)]
 pub enum Error {
-    BadConstruction,
+    // a source could not load a path
+    Load {
+        path: String,
+        source: String,
+        error: LoadError,
+    },
+    // a document could not be read in its format. the location is given if the format knows it
+    Parse {
+        path: String,
+        line: Option<usize>,
+        column: Option<usize>,
+        message: String,
+    },
+    // something in a definition is wrong, e.g. a guard that uses an unknown name
+    InvalidDefinition {
+        state: String,
+        context: Option<String>,
+        reason: String,
+    },
+    // there is no source with the name, or no default source if there is no name
+    MissingSource(Option<String>),
+    // a source does not allow a path, e.g. one that leaves its root directory
+    BadPath {
+        path: String,
+        source: String,
+        reason: String,
+    },
+    // the path is from an async source and can only be loaded with the async api
+    NeedsAsync {
+        path: String,
+        source: Option<String>,
+    },
+    // a snapshot was taken from a different definition
+    SnapshotMismatch,
+    // the api was used in a way it can not be, e.g. a submitted session was stepped again
+    Misuse(String),
+}
+
+impl Error {
+    pub fn invalid(
+        state: impl Into<String>,
+        context: Option<&str>,
+        reason: impl Into<String>,
+    ) -> Error {
+        Error::InvalidDefinition {
+            state: state.into(),
+            context: context.map(String::from),
+            reason: reason.into(),
+        }
+    }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Error::BadConstruction => write!(f, "BadConstruction"),
+            Error::Load {
+                path,
+                source,
+                error,
+            } => write!(f, "Could not load {} from {}: {}", path, source, error),
+            Error::Parse {
+                path,
+                line,
+                column,
+                message,
+            } => match (line, column) {
+                (Some(line), Some(column)) => write!(
+                    f,
+                    "Could not read {} at line {}, column {}: {}",
+                    path, line, column, message
+                ),
+                _ => write!(f, "Could not read {}: {}", path, message),
+            },
+            Error::InvalidDefinition {
+                state,
+                context,
+                reason,
+            } => match context {
+                Some(context) => write!(
+                    f,
+                    "Invalid definition of context {} in state {}: {}",
+                    context, state, reason
+                ),
+                None => write!(f, "Invalid definition of state {}: {}", state, reason),
+            },
+            Error::MissingSource(Some(name)) => write!(f, "Unknown source {}", name),
+            Error::MissingSource(None) => {
+                write!(f, "No source given and there is no default source")
+            }
+            Error::BadPath {
+                path,
+                source,
+                reason,
+            } => write!(f, "Bad path {} for source {}: {}", path, source, reason),
+            Error::NeedsAsync { path, .. } => write!(
+                f,
+                "Could not load {}: the source is async and can only be loaded with the async api",
+                path
+            ),
+            Error::SnapshotMismatch => {
+                write!(f, "The snapshot was taken from a different definition")
+            }
+            Error::Misuse(reason) => write!(f, "{}", reason),
         }
     }
 }
 
 impl StdError for Error {
-    fn description(&self) -> &str {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
         match self {
-            Error::BadConstruction => "BadConstruction",
+            Error::Load { error, .. } => Some(error),
+            _ => None,
         }
     }
 }
@@ -0,0 +1,702 @@
+use crate::value::{Kind, Value};
+use std::fmt;
+
+// a guard written as text, e.g. `Age` >= 18 and not (Country in ["DE", "AT"]).
+// names are the names of contexts or of OptionsStates, written as they are if they
+// only have letters, digits and underscores, otherwise between backticks.
+//
+// literals:  12, 1.5, "text", 'text', true, false, [1, 2, 3]
+// logic:     and or not && || !
+// compare:   == != < <= > >=
+// lists:     x in [..], text in text, list contains x, text contains text
+// numbers:   + - * / % and - in front
+// functions: len(text or list), answered(name)
+//
+// expressions are parsed and type checked when the state is built, so a broken one fails to load.
+// a name that has not been answered makes the expression false unless it is only used in answered
+#[derive(Debug, Clone)]
+pub struct Expression {
+    source: String,
+    expr: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Bool,
+    Number,
+    Text,
+    List(Box<Type>),
+    // the items of an empty list
+    Any,
+}
+
+impl Type {
+    // the type a context of this kind has in an expression
+    pub fn of(kind: Kind) -> Type {
+        match kind {
+            Kind::Integer | Kind::Decimal => Type::Number,
+            Kind::Boolean => Type::Bool,
+            _ => Type::Text,
+        }
+    }
+
+    fn matches(&self, other: &Type) -> bool {
+        match (self, other) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::List(a), Type::List(b)) => a.matches(b),
+            (a, b) => a == b,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Bool => write!(f, "bool"),
+            Type::Number => write!(f, "number"),
+            Type::Text => write!(f, "text"),
+            Type::List(item) => write!(f, "list of {}", item),
+            Type::Any => write!(f, "anything"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    In,
+    Contains,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            Op::Or => "or",
+            Op::And => "and",
+            Op::Eq => "==",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::In => "in",
+            Op::Contains => "contains",
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+            Op::Rem => "%",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Bool(bool),
+    Number(f64),
+    Text(String),
+    List(Vec<Expr>),
+    Name(String),
+    Answered(String),
+    Len(Box<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Text(String),
+    // a name or a keyword
+    Word(String),
+    // a name between backticks, never a keyword
+    Name(String),
+    Symbol(&'static str),
+}
+
+// what an expression gives while it is evaluated
+#[derive(Debug, Clone, PartialEq)]
+enum Val {
+    Bool(bool),
+    Number(f64),
+    Text(String),
+    List(Vec<Val>),
+}
+
+const SYMBOLS: [&str; 19] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "+", "-", "*", "/", "%", "(", ")", "[", "]",
+    ",",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let number: String = chars[start..i].iter().collect();
+            let number = number
+                .parse::<f64>()
+                .map_err(|_| format!("Bad number {}", number))?;
+            tokens.push(Token::Number(number));
+        } else if c == '"' || c == '\'' || c == '`' {
+            let start = i + 1;
+            i = start;
+            while i < chars.len() && chars[i] != c {
+                i += 1;
+            }
+            if i >= chars.len() {
+                return Err(format!("Missing closing {}", c));
+            }
+            let text: String = chars[start..i].iter().collect();
+            i += 1;
+            if c == '`' {
+                tokens.push(Token::Name(text));
+            } else {
+                tokens.push(Token::Text(text));
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Word(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let symbol = SYMBOLS
+                .iter()
+                .find(|x| rest.starts_with(**x))
+                .ok_or_else(|| format!("Unexpected {}", c))?;
+            tokens.push(Token::Symbol(symbol));
+            i += symbol.len();
+        }
+    }
+    Ok(tokens)
+}
+
+// how deep an expression can be nested, counting parentheses, lists, len, not and - in front
+// and operators in a row. deeper ones would overflow the stack when they are checked or evaluated
+const MAX_DEPTH: usize = 64;
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    // takes the next token if it is one of the symbols or keywords
+    fn eat(&mut self, any_of: &[&'static str]) -> Option<&'static str> {
+        let found = match self.peek() {
+            Some(Token::Symbol(symbol)) => any_of.iter().find(|x| *x == symbol),
+            Some(Token::Word(word)) => any_of.iter().find(|x| *x == word),
+            _ => None,
+        }
+        .copied();
+        if found.is_some() {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, symbol: &'static str) -> Result<(), String> {
+        match self.eat(&[symbol]) {
+            Some(_) => Ok(()),
+            None => Err(format!("Expected {}", symbol)),
+        }
+    }
+
+    // one level deeper, the parse fails once it goes below MAX_DEPTH.
+    // a failed parse is given up, so the depth is only given back on success
+    fn enter(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(format!("Expression is nested deeper than {}", MAX_DEPTH));
+        }
+        Ok(())
+    }
+
+    fn nested(&mut self, parse: fn(&mut Parser) -> Result<Expr, String>) -> Result<Expr, String> {
+        self.enter()?;
+        let expr = parse(self)?;
+        self.depth -= 1;
+        Ok(expr)
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let depth = self.depth;
+        let mut left = self.and()?;
+        while self.eat(&["||", "or"]).is_some() {
+            self.enter()?;
+            left = Expr::Binary(Op::Or, Box::new(left), Box::new(self.and()?));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let depth = self.depth;
+        let mut left = self.not()?;
+        while self.eat(&["&&", "and"]).is_some() {
+            self.enter()?;
+            left = Expr::Binary(Op::And, Box::new(left), Box::new(self.not()?));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.eat(&["!", "not"]).is_some() {
+            return Ok(Expr::Not(Box::new(self.nested(Parser::not)?)));
+        }
+        self.compare()
+    }
+
+    fn compare(&mut self) -> Result<Expr, String> {
+        let left = self.add()?;
+        let op = match self.eat(&["==", "!=", "<=", ">=", "<", ">", "in", "contains"]) {
+            Some("==") => Op::Eq,
+            Some("!=") => Op::Ne,
+            Some("<=") => Op::Le,
+            Some(">=") => Op::Ge,
+            Some("<") => Op::Lt,
+            Some(">") => Op::Gt,
+            Some("in") => Op::In,
+            Some("contains") => Op::Contains,
+            _ => return Ok(left),
+        };
+        Ok(Expr::Binary(op, Box::new(left), Box::new(self.add()?)))
+    }
+
+    fn add(&mut self) -> Result<Expr, String> {
+        let depth = self.depth;
+        let mut left = self.multiply()?;
+        while let Some(symbol) = self.eat(&["+", "-"]) {
+            self.enter()?;
+            let op = if symbol == "+" { Op::Add } else { Op::Sub };
+            left = Expr::Binary(op, Box::new(left), Box::new(self.multiply()?));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn multiply(&mut self) -> Result<Expr, String> {
+        let depth = self.depth;
+        let mut left = self.negate()?;
+        while let Some(symbol) = self.eat(&["*", "/", "%"]) {
+            self.enter()?;
+            let op = match symbol {
+                "*" => Op::Mul,
+                "/" => Op::Div,
+                _ => Op::Rem,
+            };
+            left = Expr::Binary(op, Box::new(left), Box::new(self.negate()?));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn negate(&mut self) -> Result<Expr, String> {
+        if self.eat(&["-"]).is_some() {
+            return Ok(Expr::Negate(Box::new(self.nested(Parser::negate)?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        if self.eat(&["("]).is_some() {
+            let expr = self.nested(Parser::or)?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        if self.eat(&["["]).is_some() {
+            let mut items = vec![];
+            if self.eat(&["]"]).is_some() {
+                return Ok(Expr::List(items));
+            }
+            loop {
+                items.push(self.nested(Parser::or)?);
+                if self.eat(&["]"]).is_some() {
+                    return Ok(Expr::List(items));
+                }
+                self.expect(",")?;
+            }
+        }
+
+        let token = self.peek().cloned().ok_or("Unexpected end")?;
+        self.position += 1;
+        match token {
+            Token::Number(number) => Ok(Expr::Number(number)),
+            Token::Text(text) => Ok(Expr::Text(text)),
+            Token::Name(name) => Ok(Expr::Name(name)),
+            Token::Word(word) => match word.as_str() {
+                "true" => Ok(Expr::Bool(true)),
+                "false" => Ok(Expr::Bool(false)),
+                "len" if self.eat(&["("]).is_some() => {
+                    let expr = self.nested(Parser::or)?;
+                    self.expect(")")?;
+                    Ok(Expr::Len(Box::new(expr)))
+                }
+                "answered" if self.eat(&["("]).is_some() => {
+                    let name = match self.peek().cloned() {
+                        Some(Token::Name(name)) | Some(Token::Word(name)) => name,
+                        _ => return Err(String::from("answered needs a name")),
+                    };
+                    self.position += 1;
+                    self.expect(")")?;
+                    Ok(Expr::Answered(name))
+                }
+                _ => Ok(Expr::Name(word)),
+            },
+            Token::Symbol(symbol) => Err(format!("Unexpected {}", symbol)),
+        }
+    }
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Expression, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+            depth: 0,
+        };
+        let expr = parser.or()?;
+        if parser.peek().is_some() {
+            return Err(String::from("Unexpected input after the expression"));
+        }
+        Ok(Expression {
+            source: source.to_string(),
+            expr,
+        })
+    }
+
+    pub fn get_source(&self) -> &str {
+        &self.source
+    }
+
+    // checks that the expression gives a bool.
+    // type_of gives the type of a name, nothing if there is no such name
+    pub fn check(&self, type_of: &impl Fn(&str) -> Option<Type>) -> Result<(), String> {
+        match type_check(&self.expr, type_of)? {
+            Type::Bool => Ok(()),
+            other => Err(format!("Expected a bool, found {}", other)),
+        }
+    }
+
+    // answer gives the value the user gave for a name, if they gave one
+    pub fn evaluate(&self, answer: &impl Fn(&str) -> Option<Value>) -> bool {
+        matches!(evaluate(&self.expr, answer), Some(Val::Bool(true)))
+    }
+}
+
+fn type_check(expr: &Expr, type_of: &impl Fn(&str) -> Option<Type>) -> Result<Type, String> {
+    let expect = |expr: &Expr, expected: Type| -> Result<(), String> {
+        let found = type_check(expr, type_of)?;
+        if !found.matches(&expected) {
+            return Err(format!("Expected {}, found {}", expected, found));
+        }
+        Ok(())
+    };
+
+    match expr {
+        Expr::Bool(_) => Ok(Type::Bool),
+        Expr::Number(_) => Ok(Type::Number),
+        Expr::Text(_) => Ok(Type::Text),
+        Expr::List(items) => {
+            let mut item_type = Type::Any;
+            for item in items {
+                let found = type_check(item, type_of)?;
+                if !found.matches(&item_type) {
+                    return Err(format!("Lists can not mix {} and {}", item_type, found));
+                }
+                if item_type == Type::Any {
+                    item_type = found;
+                }
+            }
+            Ok(Type::List(Box::new(item_type)))
+        }
+        Expr::Name(name) => type_of(name).ok_or_else(|| format!("Unknown name {}", name)),
+        Expr::Answered(name) => {
+            type_of(name).ok_or_else(|| format!("Unknown name {}", name))?;
+            Ok(Type::Bool)
+        }
+        Expr::Len(expr) => match type_check(expr, type_of)? {
+            Type::Text | Type::List(_) => Ok(Type::Number),
+            other => Err(format!("len needs text or a list, found {}", other)),
+        },
+        Expr::Not(expr) => {
+            expect(expr, Type::Bool)?;
+            Ok(Type::Bool)
+        }
+        Expr::Negate(expr) => {
+            expect(expr, Type::Number)?;
+            Ok(Type::Number)
+        }
+        Expr::Binary(op, left, right) => {
+            let left_type = type_check(left, type_of)?;
+            let right_type = type_check(right, type_of)?;
+            let mismatch = || format!("Can not use {} with {} and {}", op, left_type, right_type);
+            match op {
+                Op::Or | Op::And => {
+                    expect(left, Type::Bool)?;
+                    expect(right, Type::Bool)?;
+                    Ok(Type::Bool)
+                }
+                Op::Eq | Op::Ne => {
+                    if !left_type.matches(&right_type) {
+                        return Err(mismatch());
+                    }
+                    Ok(Type::Bool)
+                }
+                Op::Lt | Op::Le | Op::Gt | Op::Ge => match (&left_type, &right_type) {
+                    (Type::Number, Type::Number) | (Type::Text, Type::Text) => Ok(Type::Bool),
+                    _ => Err(mismatch()),
+                },
+                Op::In | Op::Contains => {
+                    let (item, container) = if *op == Op::In {
+                        (&left_type, &right_type)
+                    } else {
+                        (&right_type, &left_type)
+                    };
+                    match container {
+                        Type::Text if *item == Type::Text => Ok(Type::Bool),
+                        Type::List(items) if items.matches(item) => Ok(Type::Bool),
+                        _ => Err(mismatch()),
+                    }
+                }
+                Op::Add if left_type == Type::Text && right_type == Type::Text => Ok(Type::Text),
+                Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Rem => {
+                    if left_type != Type::Number || right_type != Type::Number {
+                        return Err(mismatch());
+                    }
+                    Ok(Type::Number)
+                }
+            }
+        }
+    }
+}
+
+fn to_val(value: Value) -> Option<Val> {
+    match value {
+        Value::Integer(value) => Some(Val::Number(value as f64)),
+        Value::Decimal(value) => Some(Val::Number(value)),
+        Value::Boolean(value) => Some(Val::Bool(value)),
+        Value::Empty => None,
+        value => Some(Val::Text(value.to_string())),
+    }
+}
+
+// nothing if a name has not been answered or the numbers do not work out, e.g. 1 / 0
+fn evaluate(expr: &Expr, answer: &impl Fn(&str) -> Option<Value>) -> Option<Val> {
+    match expr {
+        Expr::Bool(value) => Some(Val::Bool(*value)),
+        Expr::Number(value) => Some(Val::Number(*value)),
+        Expr::Text(value) => Some(Val::Text(value.clone())),
+        Expr::List(items) => items
+            .iter()
+            .map(|x| evaluate(x, answer))
+            .collect::<Option<Vec<Val>>>()
+            .map(Val::List),
+        Expr::Name(name) => answer(name).and_then(to_val),
+        Expr::Answered(name) => Some(Val::Bool(answer(name).and_then(to_val).is_some())),
+        Expr::Len(expr) => match evaluate(expr, answer)? {
+            Val::Text(text) => Some(Val::Number(text.chars().count() as f64)),
+            Val::List(items) => Some(Val::Number(items.len() as f64)),
+            _ => None,
+        },
+        Expr::Not(expr) => match evaluate(expr, answer)? {
+            Val::Bool(value) => Some(Val::Bool(!value)),
+            _ => None,
+        },
+        Expr::Negate(expr) => match evaluate(expr, answer)? {
+            Val::Number(value) => Some(Val::Number(-value)),
+            _ => None,
+        },
+        Expr::Binary(Op::And, left, right) => match evaluate(left, answer)? {
+            Val::Bool(false) => Some(Val::Bool(false)),
+            _ => evaluate(right, answer),
+        },
+        Expr::Binary(Op::Or, left, right) => match evaluate(left, answer) {
+            Some(Val::Bool(true)) => Some(Val::Bool(true)),
+            _ => evaluate(right, answer),
+        },
+        Expr::Binary(op, left, right) => {
+            let left = evaluate(left, answer)?;
+            let right = evaluate(right, answer)?;
+            binary(*op, left, right)
+        }
+    }
+}
+
+fn binary(op: Op, left: Val, right: Val) -> Option<Val> {
+    let result = match (op, left, right) {
+        (Op::Eq, left, right) => Val::Bool(left == right),
+        (Op::Ne, left, right) => Val::Bool(left != right),
+        (Op::Lt, Val::Number(a), Val::Number(b)) => Val::Bool(a < b),
+        (Op::Le, Val::Number(a), Val::Number(b)) => Val::Bool(a <= b),
+        (Op::Gt, Val::Number(a), Val::Number(b)) => Val::Bool(a > b),
+        (Op::Ge, Val::Number(a), Val::Number(b)) => Val::Bool(a >= b),
+        (Op::Lt, Val::Text(a), Val::Text(b)) => Val::Bool(a < b),
+        (Op::Le, Val::Text(a), Val::Text(b)) => Val::Bool(a <= b),
+        (Op::Gt, Val::Text(a), Val::Text(b)) => Val::Bool(a > b),
+        (Op::Ge, Val::Text(a), Val::Text(b)) => Val::Bool(a >= b),
+        (Op::In, Val::Text(a), Val::Text(b)) => Val::Bool(b.contains(&a)),
+        (Op::In, item, Val::List(items)) => Val::Bool(items.contains(&item)),
+        (Op::Contains, Val::Text(a), Val::Text(b)) => Val::Bool(a.contains(&b)),
+        (Op::Contains, Val::List(items), item) => Val::Bool(items.contains(&item)),
+        (Op::Add, Val::Text(a), Val::Text(b)) => Val::Text(a + &b),
+        (Op::Add, Val::Number(a), Val::Number(b)) => Val::Number(a + b),
+        (Op::Sub, Val::Number(a), Val::Number(b)) => Val::Number(a - b),
+        (Op::Mul, Val::Number(a), Val::Number(b)) => Val::Number(a * b),
+        (Op::Div, Val::Number(a), Val::Number(b)) if b != 0.0 => Val::Number(a / b),
+        (Op::Rem, Val::Number(a), Val::Number(b)) if b != 0.0 => Val::Number(a % b),
+        _ => return None,
+    };
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(name: &str) -> Option<Value> {
+        match name {
+            "Age" => Some(Value::Integer(30)),
+            "Height" => Some(Value::Decimal(1.75)),
+            "Country" => Some(Value::Text(String::from("DE"))),
+            "Likes cats" => Some(Value::Boolean(true)),
+            "Skipped" => Some(Value::Empty),
+            _ => None,
+        }
+    }
+
+    fn type_of(name: &str) -> Option<Type> {
+        match name {
+            "Age" | "Height" | "Unanswered" => Some(Type::Number),
+            "Country" | "Skipped" => Some(Type::Text),
+            "Likes cats" => Some(Type::Bool),
+            _ => None,
+        }
+    }
+
+    fn holds(source: &str) -> bool {
+        let expression = Expression::parse(source).unwrap();
+        expression.check(&type_of).unwrap();
+        expression.evaluate(&answer)
+    }
+
+    fn check(source: &str) -> Result<(), String> {
+        Expression::parse(source)?.check(&type_of)
+    }
+
+    #[test]
+    fn precedence() {
+        assert!(holds("1 + 2 * 3 == 7"));
+        assert!(holds("(1 + 2) * 3 == 9"));
+        assert!(holds("10 - 4 - 3 == 3"));
+        assert!(holds("-2 * 3 == -6"));
+        assert!(holds("7 % 4 + 1 == 4"));
+        assert!(holds("true or false and false"));
+        assert!(!holds("(true or false) and false"));
+        assert!(holds("not false and true"));
+        assert!(!holds("not (false or true)"));
+        assert!(holds("Age >= 18 && !(Country in [\"AT\", \"CH\"])"));
+    }
+
+    #[test]
+    fn in_and_contains() {
+        assert!(holds("Country in [\"DE\", \"AT\"]"));
+        assert!(!holds("Country in [\"FR\"]"));
+        assert!(holds("\"E\" in Country"));
+        assert!(holds("Country contains 'D'"));
+        assert!(holds("[1, 2, 30] contains Age"));
+        assert!(holds("Age in [30]"));
+        assert!(!holds("Age in []"));
+        assert!(holds("len([1, 2, 3]) == 3 and len(`Country`) == 2"));
+    }
+
+    #[test]
+    fn unanswered_names() {
+        assert!(!holds("Unanswered > 1"));
+        assert!(!holds("Unanswered <= 1"));
+        assert!(!holds("not (Unanswered > 1)"));
+        assert!(!holds("Skipped == \"\""));
+        assert!(holds("not answered(Unanswered)"));
+        assert!(holds("not answered(Skipped)"));
+        assert!(holds("answered(`Likes cats`)"));
+        assert!(holds("Unanswered > 1 or Age == 30"));
+        assert!(!holds("Unanswered > 1 and Age == 30"));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert!(!holds("Age / 0 > 1"));
+        assert!(!holds("Age / 0 <= 1"));
+        assert!(!holds("Age % 0 == 0"));
+        assert!(holds("Age / 0 > 1 or true"));
+        assert!(holds("Height * 2 == 3.5"));
+    }
+
+    #[test]
+    fn type_errors() {
+        assert!(check("Age").is_err());
+        assert!(check("Age == \"30\"").is_err());
+        assert!(check("Country < 3").is_err());
+        assert!(check("Country + 1 == 2").is_err());
+        assert!(check("Age in Country").is_err());
+        assert!(check("[1, \"a\"] contains 1").is_err());
+        assert!(check("not Age").is_err());
+        assert!(check("-Country == 1").is_err());
+        assert!(check("len(Age) == 1").is_err());
+        assert!(check("Unknown == 1").is_err());
+        assert!(check("answered(Unknown)").is_err());
+        assert!(check("`Likes cats` and Country + \"!\" == \"DE!\"").is_ok());
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert!(Expression::parse("1 +").is_err());
+        assert!(Expression::parse("(1 == 1").is_err());
+        assert!(Expression::parse("1 == 1 1").is_err());
+        assert!(Expression::parse("\"open").is_err());
+        assert!(Expression::parse("1 # 2").is_err());
+    }
+
+    #[test]
+    fn nesting_depth() {
+        let nested = |depth: usize| format!("{}true{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(holds(&nested(MAX_DEPTH - 1)));
+        assert!(Expression::parse(&nested(MAX_DEPTH + 1)).is_err());
+        assert!(Expression::parse(&nested(100_000)).is_err());
+        assert!(Expression::parse(&format!("{}true", "not ".repeat(100_000))).is_err());
+        assert!(Expression::parse(&format!("{}1 == 1", "-".repeat(100_000))).is_err());
+        assert!(Expression::parse(&format!("{}1 == 1", "[".repeat(100_000))).is_err());
+        assert!(Expression::parse(&format!("1{} == 1", " + 1".repeat(100_000))).is_err());
+        assert!(holds(&format!("1{} == 10", " + 1".repeat(9))));
+    }
+}
@@ -0,0 +1,80 @@
+use crate::{
+    error::Error,
+    serde_state::{SerDeState, VERSION},
+};
+
+// the formats a definition can be written in. every path of a flow can have its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Yaml,
+    #[cfg(feature = "json")]
+    Json,
+    // toml has no null, fields without a value such as the next state are left out instead
+    #[cfg(feature = "toml")]
+    Toml,
+    #[cfg(feature = "ron")]
+    Ron,
+}
+
+impl Format {
+    // the format of a path by its extension. yaml for any other extension
+    pub fn of_path(path: &str) -> Format {
+        let extension = path.rsplit_once('.').map(|(_, x)| x.to_lowercase());
+        match extension.as_deref() {
+            #[cfg(feature = "json")]
+            Some("json") => Format::Json,
+            #[cfg(feature = "toml")]
+            Some("toml") => Format::Toml,
+            #[cfg(feature = "ron")]
+            Some("ron") => Format::Ron,
+            _ => Format::Yaml,
+        }
+    }
+
+    // reads the state of a document. path is only used to tell where an error is
+    pub fn parse(&self, path: &str, string: &str) -> Result<SerDeState, Error> {
+        let parse_error = |location: Option<(usize, usize)>, message: String| Error::Parse {
+            path: path.to_string(),
+            line: location.map(|(line, _)| line),
+            column: location.map(|(_, column)| column),
+            message,
+        };
+        let state: SerDeState = match self {
+            Format::Yaml => serde_yaml::from_str(string).map_err(|error| {
+                let location = error.location().map(|x| (x.line(), x.column()));
+                parse_error(location, error.to_string())
+            })?,
+            #[cfg(feature = "json")]
+            Format::Json => serde_json::from_str(string).map_err(|error| {
+                parse_error(Some((error.line(), error.column())), error.to_string())
+            })?,
+            #[cfg(feature = "toml")]
+            Format::Toml => toml::from_str(string).map_err(|error| {
+                let location = error.span().map(|x| line_and_column(string, x.start));
+                parse_error(location, error.message().to_string())
+            })?,
+            #[cfg(feature = "ron")]
+            Format::Ron => ron::from_str(string).map_err(|error| {
+                let location = Some((error.position.line, error.position.col));
+                parse_error(location, error.code.to_string())
+            })?,
+        };
+        match state.version {
+            Some(version) if version > VERSION => Err(Error::invalid(
+                state.name,
+                None,
+                format!("Unknown version {}, at most {} is known", version, VERSION),
+            )),
+            _ => Ok(state),
+        }
+    }
+}
+
+// the line and column of a byte in a string, both starting at 1
+#[cfg(feature = "toml")]
+fn line_and_column(string: &str, index: usize) -> (usize, usize) {
+    let before = &string[..index.min(string.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|x| *x != '\n').count() + 1;
+    (line, column)
+}
@@ -1,7 +1,24 @@
+pub mod action;
 pub mod collection;
+pub mod condition;
 pub mod context;
+pub mod definition;
+pub mod diagnostic;
 pub mod error;
+pub mod expression;
+pub mod format;
+pub mod machine;
+pub mod observer;
 pub mod option;
+#[cfg(feature = "schema")]
+pub mod schema;
 pub mod serde_state;
+pub mod session;
+pub mod snapshot;
+pub mod source;
 pub mod state;
 pub mod status;
+pub mod template;
+mod trace;
+pub mod validation;
+pub mod value;
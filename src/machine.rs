@@ -0,0 +1,540 @@
+use crate::{
+    action::Actions, condition::Guard, context::Context, error::Error, expression::Type,
+    snapshot::StateKey, source::Sources, state::State, trace::event,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    ops::Range,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+// gives every machine that is created a number of its own, see StateId
+static MACHINES: AtomicUsize = AtomicUsize::new(0);
+
+// a handle to a state of a machine. only valid for the machine that created it
+// and for the machines that grew out of it by loading more states.
+// it keeps the number of that machine, so ids of other machines are never taken for its states
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StateId {
+    machine: usize,
+    index: usize,
+}
+
+impl StateId {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl fmt::Display for StateId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.index)
+    }
+}
+
+// a state that has been loaded from a path
+#[derive(Clone)]
+pub struct LoadedPath {
+    pub state: StateId,
+    // the states that were created while loading the path, including paths it loaded eagerly
+    pub states: Range<usize>,
+}
+
+impl LoadedPath {
+    pub fn contains(&self, id: StateId) -> bool {
+        self.states.contains(&id.index)
+    }
+}
+
+// the states that were built from the root string or from one path.
+// paths that were loaded eagerly while building it are documents of their own
+#[derive(Clone)]
+struct Document {
+    // the source and path the document was loaded from, paths written in it are joined to it.
+    // the root document only has one if set_root_location was called
+    location: Option<(String, String)>,
+    states: Vec<StateId>,
+}
+
+// all the states of a flow live in one arena and point to each other by StateId.
+// a machine is never changed once it is shared, states that are loaded lazily
+// through a StateHolder are appended to a copy of it (see Definition)
+#[derive(Clone)]
+pub struct Machine {
+    // the number of the machine, copies keep it
+    id: usize,
+    // the states in the order they were created, in chunks. a copy shares the chunks of the
+    // machine it was copied from and adds the states it builds in a chunk of its own
+    chunks: Vec<Arc<Vec<State>>>,
+    // the index of the first state of every chunk
+    starts: Vec<usize>,
+    root: StateId,
+    sources: Sources,
+    // states that have been loaded from a path, by the source and the path.
+    // every StateHolder with the same source and path shares them
+    cache: HashMap<(String, String), LoadedPath>,
+    // the root document comes first, paths follow in the order they were loaded
+    documents: Vec<Document>,
+    // the document every state was built from and its position in it
+    origins: Vec<(usize, usize)>,
+    // the documents that are being built, the innermost last
+    building: Vec<usize>,
+    // the strings of the paths that have been loaded, by their source and path.
+    // only kept while loading, see set_fetched
+    fetched: HashMap<(String, String), String>,
+}
+
+impl Machine {
+    pub fn new(sources: Sources) -> Machine {
+        let id = MACHINES.fetch_add(1, Ordering::Relaxed);
+        Machine {
+            id,
+            chunks: vec![],
+            starts: vec![],
+            root: StateId {
+                machine: id,
+                index: 0,
+            },
+            sources,
+            cache: HashMap::new(),
+            documents: vec![Document {
+                location: None,
+                states: vec![],
+            }],
+            origins: vec![],
+            building: vec![0],
+            fetched: HashMap::new(),
+        }
+    }
+
+    pub fn get_root(&self) -> StateId {
+        self.root
+    }
+
+    // the root document is done once its root is set.
+    // paths that lead back to the location of the root get the root from then on
+    pub fn set_root(&mut self, root: StateId) {
+        self.root = root;
+        self.building.clear();
+        if let Some(location) = self.documents[0].location.clone() {
+            let states = 0..self.len();
+            self.cache.entry(location).or_insert(LoadedPath {
+                state: root,
+                states,
+            });
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match (self.starts.last(), self.chunks.last()) {
+            (Some(start), Some(chunk)) => start + chunk.len(),
+            _ => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // the chunk of a state and its position in it
+    fn locate(&self, id: StateId) -> Option<(usize, usize)> {
+        if !self.contains(id) {
+            return None;
+        }
+        let chunk = self.starts.partition_point(|x| *x <= id.index) - 1;
+        Some((chunk, id.index - self.starts[chunk]))
+    }
+
+    // the state itself, even if it is a StateHolder. None for ids of other machines
+    pub fn get(&self, id: StateId) -> Option<&State> {
+        let (chunk, index) = self.locate(id)?;
+        Some(&self.chunks[chunk][index])
+    }
+
+    // like get, for ids that were taken from this machine or checked with contains
+    pub(crate) fn state(&self, id: StateId) -> &State {
+        match self.get(id) {
+            Some(state) => state,
+            None => panic!("State {} is not in the machine", id),
+        }
+    }
+
+    // false for the StateIds of other machines that this one did not grow out of
+    pub fn contains(&self, id: StateId) -> bool {
+        id.machine == self.id && id.index < self.len()
+    }
+
+    fn id_at(&self, index: usize) -> StateId {
+        StateId {
+            machine: self.id,
+            index,
+        }
+    }
+
+    fn states(&self) -> impl Iterator<Item = &State> {
+        self.chunks.iter().flat_map(|x| x.iter())
+    }
+
+    // gives the state a StateHolder stands for, or the state itself for other states.
+    // fails if the state has not been loaded or is not in this machine
+    pub fn get_state(&self, id: StateId) -> Result<&State, Error> {
+        if !self.contains(id) {
+            return Err(Machine::not_contained(id));
+        }
+        match self.get_loaded(id) {
+            Some(state) => Ok(self.state(state)),
+            None => Err(Error::Misuse(format!(
+                "{} has to be loaded first",
+                self.get_name(id)
+            ))),
+        }
+    }
+
+    // only used while building, states are shared between machines afterwards
+    pub fn get_mut(&mut self, id: StateId) -> Option<&mut State> {
+        let (chunk, index) = self.locate(id)?;
+        Arc::get_mut(&mut self.chunks[chunk]).map(|x| &mut x[index])
+    }
+
+    pub fn push(&mut self, state: State) -> StateId {
+        let id = self.id_at(self.len());
+        let document = *self.building.last().unwrap_or(&0);
+        //the last chunk is shared once the machine has been copied, so a copy starts its own
+        match self.chunks.last_mut().and_then(Arc::get_mut) {
+            Some(chunk) => chunk.push(state),
+            None => {
+                self.starts.push(id.index);
+                self.chunks.push(Arc::new(vec![state]));
+            }
+        }
+        self.origins
+            .push((document, self.documents[document].states.len()));
+        self.documents[document].states.push(id);
+        event!(TRACE, state = %id, name = %self.get_name(id), "state created");
+        id
+    }
+
+    // sets where the root string was loaded from, so the paths written in it can be joined to it
+    pub fn set_root_location(&mut self, source: Option<&str>, path: &str) -> Result<(), Error> {
+        let source = self.sources.get_name(source)?.to_string();
+        self.documents[0].location = Some((source, path.to_string()));
+        Ok(())
+    }
+
+    // gives the path to load for a path written in the document that is being built.
+    // paths of the source the document was loaded from are relative to it
+    pub fn join(&self, source: Option<&str>, path: &str) -> Result<String, Error> {
+        let source = self.sources.get_name(source)?;
+        let document = &self.documents[*self.building.last().unwrap_or(&0)];
+        let base = match &document.location {
+            Some((base_source, base)) if base_source == source => Some(base.as_str()),
+            _ => None,
+        };
+        self.sources.join(Some(source), base, path)
+    }
+
+    // gives the address of a state that does not depend on the order paths were loaded in.
+    // None for ids of other machines
+    pub fn get_key(&self, id: StateId) -> Option<StateKey> {
+        self.contains(id).then(|| self.key(id))
+    }
+
+    // like get_key, for ids that were taken from this machine
+    pub(crate) fn key(&self, id: StateId) -> StateKey {
+        let (document, index) = self.origins[id.index];
+        //the root document keeps no location in the key, it may be loaded from somewhere else next time
+        let (source, path) = match &self.documents[document].location {
+            Some((source, path)) if document > 0 => (Some(source.clone()), Some(path.clone())),
+            _ => (None, None),
+        };
+        StateKey {
+            path,
+            source,
+            index,
+            name: self.get_name(id),
+        }
+    }
+
+    // finds the state of a key, if its path has been loaded and it still has the same name
+    pub fn find_key(&self, key: &StateKey) -> Option<StateId> {
+        let document = match &key.path {
+            None => self.documents.first()?,
+            Some(path) => self.documents.iter().skip(1).find(|x| match &x.location {
+                Some(location) => Machine::is_location(location, key.source.as_deref(), path),
+                None => false,
+            })?,
+        };
+        let id = *document.states.get(key.index)?;
+        if self.get_name(id) != key.name {
+            return None;
+        }
+        Some(id)
+    }
+
+    // finds a StateHolder that loads the path from the source with the name,
+    // or from any source if there is no name
+    pub fn find_holder(&self, source: Option<&str>, path: &str) -> Option<StateId> {
+        self.states()
+            .position(|x| match x {
+                State::StateHolder(holder) => {
+                    Machine::is_location(&holder.get_location(), source, path)
+                }
+                _ => false,
+            })
+            .map(|x| self.id_at(x))
+    }
+
+    fn is_location(location: &(String, String), source: Option<&str>, path: &str) -> bool {
+        location.1 == path && source.is_none_or(|x| x == location.0)
+    }
+
+    // sums up what the answers given in a state depend on: the names and kinds of its
+    // contexts or the names of its options. snapshots keep it to find states that changed.
+    // None for StateHolders and ids of other machines
+    pub fn get_fingerprint(&self, id: StateId) -> Option<u64> {
+        let parts: Vec<String> = match self.get(id)? {
+            State::ContextState(state) => state
+                .contexts
+                .iter()
+                .map(|x| match x {
+                    Context::StateContext(context) => {
+                        format!("{}:{:?}", x.get_name(), context.kind)
+                    }
+                    Context::StateOptionsContext(_) => format!("{}:Options", x.get_name()),
+                })
+                .collect(),
+            State::OptionsState(state) => state.options.iter().map(|x| x.get_name()).collect(),
+            State::StateHolder(_) => return None,
+        };
+        //fnv-1a, unlike the hashers of std it stays the same across versions of rust
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for part in &parts {
+            for byte in part.len().to_le_bytes().iter().chain(part.as_bytes()) {
+                hash ^= u64::from(*byte);
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+        Some(hash)
+    }
+
+    // empty for ids of other machines
+    pub fn get_name(&self, id: StateId) -> String {
+        self.get(id).map(|x| x.get_name()).unwrap_or_default()
+    }
+
+    // an OptionsState that has been created for the options of a context
+    pub fn is_context_options(&self, id: StateId) -> bool {
+        let parent = match self.get(id) {
+            Some(State::OptionsState(state)) => state.parent,
+            _ => None,
+        };
+        match parent.map(|x| self.state(x)) {
+            Some(State::ContextState(state)) => {
+                state.contexts.iter().any(|x| x.output() == Some(id))
+            }
+            _ => false,
+        }
+    }
+
+    // the type of what was answered for a name, as seen from a state.
+    // looks for the name like the session does when it evaluates a guard
+    pub fn type_of(&self, id: StateId, name: &str) -> Option<Type> {
+        let mut current = Some(id);
+        while let Some(id) = current {
+            match self.get(id) {
+                Some(State::ContextState(state)) => {
+                    let context = state.contexts.iter().find(|x| x.get_name() == name);
+                    match context {
+                        Some(Context::StateContext(state_context)) => {
+                            return Some(Type::of(state_context.kind))
+                        }
+                        Some(Context::StateOptionsContext(_)) => return Some(Type::Text),
+                        None => {}
+                    }
+                    current = state.parent;
+                }
+                Some(State::OptionsState(state)) => {
+                    if state.name == name && !self.is_context_options(id) {
+                        return Some(Type::Text);
+                    }
+                    current = state.parent;
+                }
+                _ => current = None,
+            }
+        }
+        None
+    }
+
+    // type checks the expressions of a state that has been built
+    pub fn check_guards(&self, id: StateId) -> Result<(), Error> {
+        let type_of = |name: &str| self.type_of(id, name);
+        let check = |guard: Option<&Guard>, context: Option<&str>| -> Result<(), Error> {
+            if let Some(Guard::Expression(expression)) = guard {
+                expression.check(&type_of).map_err(|reason| {
+                    let reason = format!("Bad expression {}: {}", expression.get_source(), reason);
+                    Error::invalid(self.get_name(id), context, reason)
+                })?;
+            }
+            Ok(())
+        };
+
+        match self.get(id) {
+            None => return Err(Machine::not_contained(id)),
+            Some(State::OptionsState(state)) => {
+                for option in &state.options {
+                    check(option.get_when(), None)?;
+                }
+            }
+            Some(State::ContextState(state)) => {
+                for context in &state.contexts {
+                    check(context.get_when(), Some(&context.get_name()))?;
+                    //the options of a context are checked with it
+                    if let Some(options_state) = context.output() {
+                        self.check_guards(options_state)?;
+                    }
+                }
+                for branch in &state.branches {
+                    check(Some(&branch.when), None)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // checks that the actions named by the states from the index on are all there,
+    // so an unknown one is found when the states are loaded and not once it should run
+    pub fn check_actions(&self, actions: &Actions, from: usize) -> Result<(), Error> {
+        for state in self.states().skip(from) {
+            let names: Vec<&str> = match state {
+                State::ContextState(state) => state.action.as_deref().into_iter().collect(),
+                State::OptionsState(state) => state
+                    .options
+                    .iter()
+                    .filter_map(|x| x.get_action())
+                    .collect(),
+                State::StateHolder(_) => vec![],
+            };
+            if let Some(name) = names.into_iter().find(|x| !actions.contains(x)) {
+                let reason = format!("Unknown action {}", name);
+                return Err(Error::invalid(state.get_name(), None, reason));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_sources(&self) -> &Sources {
+        &self.sources
+    }
+
+    // gives the state a StateHolder stands for, if it has been loaded.
+    // other states stand for themselves
+    pub fn get_loaded(&self, id: StateId) -> Option<StateId> {
+        match self.get(id)? {
+            State::StateHolder(holder) => self.cache.get(&holder.get_location()).map(|x| x.state),
+            _ => Some(id),
+        }
+    }
+
+    // gives what has been loaded for a StateHolder
+    pub fn get_loaded_path(&self, id: StateId) -> Option<&LoadedPath> {
+        match self.get(id)? {
+            State::StateHolder(holder) => self.cache.get(&holder.get_location()),
+            _ => None,
+        }
+    }
+
+    // if two states were built from the same document, the root string or one path.
+    // the states of the paths a path loaded eagerly are from documents of their own
+    pub fn is_same_document(&self, a: StateId, b: StateId) -> bool {
+        self.contains(a) && self.contains(b) && self.origins[a.index].0 == self.origins[b.index].0
+    }
+
+    fn not_contained(id: StateId) -> Error {
+        Error::Misuse(format!("State {} is not in the machine", id))
+    }
+
+    fn is_building(&self, location: &(String, String)) -> bool {
+        self.building
+            .iter()
+            .any(|x| self.documents[*x].location.as_ref() == Some(location))
+    }
+
+    // loads every path, including the lazy ones and the paths they lead to
+    pub fn resolve_all(&mut self) -> Result<(), Error> {
+        let mut id = 0;
+        while id < self.len() {
+            self.resolve(self.id_at(id))?;
+            id += 1;
+        }
+        Ok(())
+    }
+
+    // gives the state a StateHolder stands for, loading it into this machine if needed.
+    // other states stand for themselves
+    pub fn resolve(&mut self, id: StateId) -> Result<StateId, Error> {
+        if !self.contains(id) {
+            return Err(Machine::not_contained(id));
+        }
+        if let Some(state) = self.get_loaded(id) {
+            return Ok(state);
+        }
+        let (parent, location) = match self.state(id) {
+            State::StateHolder(holder) => (holder.parent, holder.get_location()),
+            _ => return Ok(id),
+        };
+        let (source, path) = (Some(location.0.as_str()), location.1.as_str());
+
+        //an eager path that leads back to a document that is still being built would load
+        //it again and again. it stays lazy instead and is found in the cache once that
+        //document is done
+        if self.is_building(&location) {
+            return Ok(id);
+        }
+
+        #[cfg(feature = "tracing")]
+        let (_span, started) = (
+            tracing::debug_span!("load", path = %path, source = %location.0).entered(),
+            std::time::Instant::now(),
+        );
+        let string = match self.fetched.get(&location) {
+            Some(string) => string.clone(),
+            None => self.sources.load(source, path)?,
+        };
+        self.fetched.insert(location.clone(), string.clone());
+        let state = self.sources.parse(source, path, &string)?;
+        let start = self.len();
+        self.documents.push(Document {
+            location: Some(location.clone()),
+            states: vec![],
+        });
+        self.building.push(self.documents.len() - 1);
+        let state = state.into_state(self, parent);
+        self.building.pop();
+        let state = state?;
+        let states = start..self.len();
+        event!(
+            DEBUG,
+            states = states.len(),
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "path loaded"
+        );
+        self.cache.insert(location, LoadedPath { state, states });
+        Ok(state)
+    }
+
+    // strings of paths that were loaded beforehand, e.g. from async sources.
+    // resolve takes them instead of loading the paths again and adds the ones it loads
+    pub fn set_fetched(&mut self, fetched: HashMap<(String, String), String>) {
+        self.fetched = fetched;
+    }
+
+    // gives the strings of every path resolve got, see set_fetched
+    pub fn take_fetched(&mut self) -> HashMap<(String, String), String> {
+        std::mem::take(&mut self.fetched)
+    }
+}
@@ -0,0 +1,58 @@
+use crate::{collection::Collection, machine::StateId, session::Session};
+
+// gets told what happens in a session, e.g. for logging, metrics or side effects.
+// every method does nothing by default. the session is given as it is after the step,
+// the states can be looked up in its machine.
+// observers are only called once a step is done, an async step that has to wait for a
+// state to be loaded does not call them twice
+pub trait Observer: Send + Sync {
+    // the session went to a state, or started in its root
+    fn on_enter(&self, session: &Session, state: StateId) {
+        let _ = (session, state);
+    }
+
+    // the session left a state for another one
+    fn on_exit(&self, session: &Session, state: StateId) {
+        let _ = (session, state);
+    }
+
+    // an input was given to a state. the reason is given if it was rejected
+    fn on_input(&self, session: &Session, state: StateId, input: &str, reason: Option<&str>) {
+        let _ = (session, state, input, reason);
+    }
+
+    // the user went back from a state
+    fn on_back(&self, session: &Session, state: StateId) {
+        let _ = (session, state);
+    }
+
+    // the session submitted with these answers
+    fn on_submit(&self, session: &Session, collections: &[Collection]) {
+        let _ = (session, collections);
+    }
+}
+
+// what happened in a step, kept until the step is done
+#[derive(Clone)]
+pub(crate) enum Event {
+    Enter(StateId),
+    Exit(StateId),
+    // a rejected input has a reason, even if it is empty
+    Input(StateId, String, Option<String>),
+    Back(StateId),
+    Submit(Vec<Collection>),
+}
+
+impl Event {
+    pub(crate) fn notify(&self, observer: &dyn Observer, session: &Session) {
+        match self {
+            Event::Enter(state) => observer.on_enter(session, *state),
+            Event::Exit(state) => observer.on_exit(session, *state),
+            Event::Input(state, input, reason) => {
+                observer.on_input(session, *state, input, reason.as_deref())
+            }
+            Event::Back(state) => observer.on_back(session, *state),
+            Event::Submit(collections) => observer.on_submit(session, collections),
+        }
+    }
+}
@@ -1,70 +1,68 @@
-use crate::state::State;
-use parking_lot::RwLock;
-use std::{error::Error as StdError, sync::Arc};
+use crate::{condition::Guard, machine::StateId};
 
 pub struct StateOption {
     pub name: String,
-    pub state: Option<Arc<RwLock<State>>>,
+    pub state: Option<StateId>,
     pub submit: bool,
     pub reset: bool,
+    // the option is only listed if the condition holds
+    pub when: Option<Guard>,
+    // the action that runs when the option is chosen, see Action
+    pub action: Option<String>,
+    // the state to go to if the action fails
+    pub failure: Option<StateId>,
 }
 
 impl StateOption {
     pub fn new(
         name: String,
-        state: Option<Arc<RwLock<State>>>,
+        state: Option<StateId>,
         submit: bool,
         reset: bool,
+        when: Option<Guard>,
+        action: Option<String>,
+        failure: Option<StateId>,
     ) -> StateOption {
         StateOption {
             name,
             state,
             submit,
             reset,
+            when,
+            action,
+            failure,
         }
     }
 
-    pub fn destroy(&mut self, destroy_state: bool) {
-        if destroy_state {
-            if let Some(state) = &self.state {
-                state.write().destroy(destroy_state);
-            }
-        }
-        self.state = None;
-    }
-
     pub fn input(&self, input: &String) -> bool {
-        if &self.name == input {
-            return true;
-        }
-        false
+        &self.name == input
     }
 
     pub fn get_name(&self) -> String {
         self.name.clone()
     }
 
-    // if reset is defined, reset the index of the state
-    pub fn get_state(&mut self) -> Result<Option<Arc<RwLock<State>>>, Box<dyn StdError>> {
-        if let Some(state) = &self.state {
-            let mut nxt = state.write().into_state_sandwich()?;
-            if nxt.is_some() {
-                let s = nxt.take().unwrap();
-                if self.reset {
-                    s.write().reset_index();
-                }
-                return Ok(Some(s));
-            } else {
-                if self.reset {
-                    self.state.as_ref().unwrap().write().reset_index();
-                }
-                return Ok(self.state.clone());
-            }
-        }
-        Ok(None)
+    pub fn get_state(&self) -> Option<StateId> {
+        self.state
     }
 
     pub fn get_submit(&self) -> bool {
         self.submit
     }
+
+    pub fn get_reset(&self) -> bool {
+        self.reset
+    }
+
+    pub fn get_when(&self) -> Option<&Guard> {
+        self.when.as_ref()
+    }
+
+    pub fn get_action(&self) -> Option<&str> {
+        self.action.as_deref()
+    }
+
+    pub fn get_failure(&self) -> Option<StateId> {
+        self.failure
+    }
 }
@@ -0,0 +1,255 @@
+use crate::serde_state::{
+    SerDeContext, SerDeContextState, SerDeIntoState, SerDeOption, SerDeOptionsContext, SerDePath,
+    SerDeState,
+};
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{
+        ArrayValidation, InstanceType, Metadata, RootSchema, Schema, SchemaObject, SingleOrVec,
+        SubschemaValidation,
+    },
+    schema_for, JsonSchema,
+};
+use std::error::Error as StdError;
+
+// the json schema of a definition document, generated from the types it is read into.
+// an enum like the type of a state is an object with a single key like {"Options": ...}
+// in json, toml and ron documents, and a tag like !Options in yaml. editors check a
+// tagged value by its content once they are told about the tags (e.g. customTags of the
+// yaml extension), so the contents of the variants are allowed too, null for the tags
+// without content like !Normal
+pub fn schema() -> RootSchema {
+    let mut schema = schema_for!(SerDeState);
+    for (name, definition) in schema.definitions.iter_mut() {
+        if let Schema::Object(definition) = definition {
+            allow_tags(name, definition);
+        }
+    }
+    schema
+}
+
+pub fn schema_json() -> Result<String, Box<dyn StdError>> {
+    Ok(serde_json::to_string_pretty(&schema())?)
+}
+
+// adds the contents of the variants to the schema of the enum with the name, see schema.
+// the variants are the schemas of one_of: a string for the variants without content
+// and an object with a single key for every other one
+fn allow_tags(name: &str, schema: &mut SchemaObject) {
+    let subschemas = match schema.subschemas.as_mut() {
+        Some(subschemas) => subschemas,
+        None => return,
+    };
+    let variants = match subschemas.one_of.take() {
+        Some(variants) => variants,
+        None => return,
+    };
+
+    let mut contents = vec![];
+    for variant in &variants {
+        let variant = match variant {
+            Schema::Object(variant) => variant,
+            Schema::Bool(_) => continue,
+        };
+        if variant.enum_values.is_some() {
+            contents.push(
+                SchemaObject {
+                    instance_type: Some(InstanceType::Null.into()),
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+        //a variant that holds the enum itself, like !Not of a condition, has a content that
+        //one of the other variants already allows. checking it would never end
+        let itself = format!("#/definitions/{}", name);
+        if let Some(object) = &variant.object {
+            if object.properties.len() == 1 {
+                contents.extend(
+                    object
+                        .properties
+                        .values()
+                        .filter(|x| match x {
+                            Schema::Object(x) => x.reference.as_ref() != Some(&itself),
+                            Schema::Bool(_) => true,
+                        })
+                        .cloned(),
+                );
+            }
+        }
+    }
+    //the content of one variant may look like the content of another one, e.g. two
+    //strings, so the schemas can not be exclusive anymore
+    subschemas.any_of = Some(variants.into_iter().chain(contents).collect());
+}
+
+// one of the schemas, for the types that can be written in more than one way
+fn any_of(description: &str, schemas: Vec<Schema>) -> Schema {
+    SchemaObject {
+        metadata: Some(Box::new(Metadata {
+            description: Some(description.to_string()),
+            ..Default::default()
+        })),
+        subschemas: Some(Box::new(SubschemaValidation {
+            any_of: Some(schemas),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+    .into()
+}
+
+impl JsonSchema for SerDePath {
+    fn schema_name() -> String {
+        String::from("SerDePath")
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        #[derive(JsonSchema)]
+        #[allow(dead_code)]
+        struct PathFields {
+            path: String,
+            lazy: Option<bool>,
+            #[serde(alias = "loader")]
+            source: Option<String>,
+        }
+
+        //[path, lazy, source], where lazy and source can be left out
+        let positional = SchemaObject {
+            instance_type: Some(InstanceType::Array.into()),
+            array: Some(Box::new(ArrayValidation {
+                items: Some(SingleOrVec::Vec(vec![
+                    gen.subschema_for::<String>(),
+                    gen.subschema_for::<Option<bool>>(),
+                    gen.subschema_for::<Option<String>>(),
+                ])),
+                min_items: Some(1),
+                max_items: Some(3),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        any_of(
+            "a path, [path, lazy, source] or a map with these fields",
+            vec![
+                gen.subschema_for::<String>(),
+                positional.into(),
+                gen.subschema_for::<PathFields>(),
+            ],
+        )
+    }
+}
+
+impl JsonSchema for SerDeContextState {
+    fn schema_name() -> String {
+        String::from("SerDeContextState")
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        #[derive(JsonSchema)]
+        #[allow(dead_code)]
+        struct ContextStateFields {
+            contexts: Vec<SerDeContext>,
+            #[serde(default)]
+            submit: bool,
+            next: Option<Box<SerDeIntoState>>,
+            action: Option<String>,
+            failure: Option<Box<SerDeIntoState>>,
+        }
+
+        any_of(
+            "contexts, submit and next, or [contexts, submit, next] in version 1",
+            vec![
+                gen.subschema_for::<ContextStateFields>(),
+                gen.subschema_for::<(Vec<SerDeContext>, bool, Option<Box<SerDeIntoState>>)>(),
+            ],
+        )
+    }
+}
+
+impl JsonSchema for SerDeOptionsContext {
+    fn schema_name() -> String {
+        String::from("SerDeOptionsContext")
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        #[derive(JsonSchema)]
+        #[allow(dead_code)]
+        struct OptionsContextFields {
+            options: Vec<SerDeOption>,
+            other_label: String,
+            other_question: String,
+        }
+
+        any_of(
+            "options, other_label and other_question, or [options, other_label, other_question] in version 1",
+            vec![
+                gen.subschema_for::<OptionsContextFields>(),
+                gen.subschema_for::<(Vec<SerDeOption>, String, String)>(),
+            ],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonschema::JSONSchema;
+    use serde_json::Value as Json;
+    use serde_yaml::Value as Yaml;
+    use std::fs;
+
+    // the value an editor checks against the schema, tags are left out and only their content is checked
+    fn untagged(value: Yaml) -> Json {
+        match value {
+            Yaml::Tagged(tagged) => untagged(tagged.value),
+            Yaml::Sequence(items) => Json::Array(items.into_iter().map(untagged).collect()),
+            Yaml::Mapping(mapping) => Json::Object(
+                mapping
+                    .into_iter()
+                    .map(|(key, value)| (key.as_str().unwrap().to_string(), untagged(value)))
+                    .collect(),
+            ),
+            scalar => serde_json::to_value(scalar).unwrap(),
+        }
+    }
+
+    fn check(schema: &JSONSchema, path: &str, document: &Json) {
+        if let Err(errors) = schema.validate(document) {
+            let errors: Vec<String> = errors
+                .map(|x| format!("{} at {}", x, x.instance_path))
+                .collect();
+            panic!("{} does not match the schema: {:?}", path, errors);
+        }
+    }
+
+    #[test]
+    fn examples_match_the_schema() {
+        let schema = serde_json::to_value(schema()).unwrap();
+        let schema = JSONSchema::compile(&schema).unwrap();
+        eprintln!("compiled");
+
+        let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/states");
+        let mut checked = 0;
+        for entry in fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().and_then(|x| x.to_str()) != Some("yaml") {
+                continue;
+            }
+            let path = path.to_string_lossy().to_string();
+            let string = fs::read_to_string(&path).unwrap();
+
+            //written with tags, as the examples are
+            check(
+                &schema,
+                &path,
+                &untagged(serde_yaml::from_str(&string).unwrap()),
+            );
+            //written with single keys, as json documents are
+            let state: SerDeState = serde_yaml::from_str(&string).unwrap();
+            check(&schema, &path, &serde_json::to_value(&state).unwrap());
+            checked += 1;
+        }
+        assert!(checked > 0);
+    }
+}
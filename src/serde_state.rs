@@ -1,16 +1,47 @@
 use crate::{
+    action::Actions,
+    condition::{Condition, Guard},
     context::{Context, StateContext, StateOptionsContext},
-    error::Error as StateError,
+    definition::Definition,
+    diagnostic::{self, Diagnostic},
+    error::Error,
+    expression::Expression,
+    machine::{Machine, StateId},
     option::StateOption,
-    state::StateHolder,
-    state::{ContextState, OptionsState, State},
+    source::Sources,
+    state::{Branch, ContextState, OptionsState, State, StateHolder},
+    validation::Rule,
+    value::Kind,
 };
-use parking_lot::RwLock;
-use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, error::Error as StdError, sync::Arc};
+use regex::Regex;
+use serde::{
+    de::{
+        self,
+        value::{EnumAccessDeserializer, MapAccessDeserializer},
+        EnumAccess, MapAccess, SeqAccess, Visitor,
+    },
+    Deserialize, Deserializer, Serialize,
+};
+#[cfg(feature = "async")]
+use std::collections::HashMap;
+use std::fmt;
+
+// states are only built into machines that are not shared yet
+fn shared() -> Error {
+    Error::Misuse(String::from("State is already shared"))
+}
+
+// the version of the definition format that is written. documents without a version are
+// version 1, which wrote context states and options contexts as positional lists.
+// these are upgraded to the named fields of version 2 as they are read
+pub const VERSION: u32 = 2;
 
 #[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct SerDeState {
+    // only read for the state at the top of a document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
     pub name: String,
     pub description: String,
     pub r#type: StateType,
@@ -19,140 +50,290 @@ pub struct SerDeState {
 impl SerDeState {
     pub fn into_state(
         self,
-        parent: Option<Arc<RwLock<State>>>,
-        how_to_get_string: Vec<fn(String) -> Result<String, Box<dyn StdError>>>,
-        cache: Arc<RwLock<HashMap<String, Arc<RwLock<State>>>>>,
-    ) -> Result<Result<Arc<RwLock<State>>, StateError>, Box<dyn StdError>> {
-        let state: Arc<RwLock<State>> = match self.r#type {
-            StateType::Context(contexts, submit, next) => {
-                let state: Arc<RwLock<State>> = Arc::new(RwLock::new(State::ContextState(
-                    ContextState::new(self.name, self.description, parent, None, vec![], submit),
+        machine: &mut Machine,
+        parent: Option<StateId>,
+    ) -> Result<StateId, Error> {
+        let name = self.name.clone();
+        let state = match self.r#type {
+            StateType::Context(SerDeContextState {
+                contexts,
+                submit,
+                next,
+                action,
+                failure,
+            }) => {
+                let state = machine.push(State::ContextState(ContextState::new(
+                    self.name,
+                    self.description,
+                    parent,
+                    None,
+                    vec![],
+                    submit,
                 )));
 
                 let contexts: Vec<Context> = contexts
                     .into_iter()
-                    .map(|x| {
-                        x.into_context(
-                            Some(state.clone()),
-                            how_to_get_string.clone(),
-                            cache.clone(),
-                        )
-                    })
-                    .collect::<Result<Result<Vec<Context>, StateError>, Box<dyn StdError>>>()??;
-                state.write().set_contexts(contexts);
+                    .map(|x| x.into_context(machine, Some(state)))
+                    .collect::<Result<Vec<Context>, Error>>()?;
+                machine
+                    .get_mut(state)
+                    .ok_or_else(shared)?
+                    .set_contexts(contexts)?;
 
                 if let Some(next) = next {
-                    let next_state = next.into_into_state(
-                        Some(state.clone()),
-                        how_to_get_string.clone(),
-                        cache.clone(),
-                    )?;
-                    state.write().set_next(Some(next_state?));
+                    //only the next state can depend on the answers
+                    let branches = match *next {
+                        SerDeIntoState::When(when, next) => vec![SerDeBranch {
+                            when: Some(when),
+                            state: *next,
+                        }],
+                        SerDeIntoState::Branches(branches) => branches,
+                        next => vec![SerDeBranch {
+                            when: None,
+                            state: next,
+                        }],
+                    };
+
+                    let mut default = None;
+                    let mut guarded = vec![];
+                    for branch in branches {
+                        let next_state = branch.state.into_into_state(machine, Some(state))?;
+                        match branch.when {
+                            Some(when) => guarded.push(Branch {
+                                when: when
+                                    .into_guard()
+                                    .map_err(|reason| Error::invalid(&name, None, reason))?,
+                                state: next_state,
+                            }),
+                            None if default.is_none() => default = Some(next_state),
+                            //only one branch can be the default
+                            None => {
+                                let reason = "More than one next state has no when";
+                                return Err(Error::invalid(name, None, reason));
+                            }
+                        }
+                    }
+                    let state_mut = machine.get_mut(state).ok_or_else(shared)?;
+                    state_mut.set_next(default)?;
+                    state_mut.set_branches(guarded)?;
+                }
+
+                if action.is_some() || failure.is_some() {
+                    let failure = failure
+                        .map(|x| x.into_into_state(machine, Some(state)))
+                        .transpose()?;
+                    machine
+                        .get_mut(state)
+                        .ok_or_else(shared)?
+                        .set_action(action, failure)?;
                 }
                 state
             }
             StateType::Options(options) => {
-                let state: Arc<RwLock<State>> = Arc::new(RwLock::new(State::OptionsState(
-                    OptionsState::new(self.name, self.description, parent, vec![]),
+                let state = machine.push(State::OptionsState(OptionsState::new(
+                    self.name,
+                    self.description,
+                    parent,
+                    vec![],
                 )));
                 let options: Vec<StateOption> = options
                     .into_iter()
-                    .map(|x| {
-                        x.into_option(
-                            Some(state.clone()),
-                            None,
-                            how_to_get_string.clone(),
-                            cache.clone(),
-                        )
-                    })
-                    .collect::<Result<Result<Vec<StateOption>, StateError>, Box<dyn StdError>>>(
-                    )??;
-                state.write().set_options(options);
+                    .map(|x| x.into_option(machine, Some(state), None))
+                    .collect::<Result<Vec<StateOption>, Error>>()?;
+                machine
+                    .get_mut(state)
+                    .ok_or_else(shared)?
+                    .set_options(options)?;
                 state
             }
         };
-        Ok(Ok(state))
+        machine.check_guards(state)?;
+        Ok(state)
     }
 
+    // loads the root state from the path with the named source, or with the default source.
+    // the sources are also used for the paths the states load
     pub fn create_from_yaml_str(
-        how_to_get_string: Vec<fn(String) -> Result<String, Box<dyn StdError>>>,
+        sources: Sources,
         name: String,
-        which_function: usize,
-    ) -> Result<Result<Arc<RwLock<State>>, StateError>, Box<dyn StdError>> {
-        let cache: Arc<RwLock<HashMap<String, Arc<RwLock<State>>>>> =
-            Arc::new(RwLock::new(HashMap::new()));
-        let function = how_to_get_string
-            .get(which_function)
-            .ok_or("Function not found")?;
-        let string = function(name)?;
-        let state: SerDeState = serde_yaml::from_str(&string)?;
-        Ok(state.into_state(None, how_to_get_string, cache)?)
+        source: Option<&str>,
+    ) -> Result<Definition, Error> {
+        let name = sources.join(source, None, &name)?;
+        let state = sources.load_state(source, &name)?;
+        let mut machine = Machine::new(sources);
+        machine.set_root_location(source, &name)?;
+        let root = state.into_state(&mut machine, None)?;
+        machine.set_root(root);
+        Ok(Definition::new(machine))
+    }
+
+    // checks the definition the root path leads to without building sessions, see diagnostic::validate
+    pub fn validate(sources: &Sources, name: &str, source: Option<&str>) -> Vec<Diagnostic> {
+        diagnostic::validate(sources, name, source)
+    }
+
+    // checks like validate and that every action the states name is one of the actions
+    pub fn validate_with_actions(
+        sources: &Sources,
+        actions: &Actions,
+        name: &str,
+        source: Option<&str>,
+    ) -> Vec<Diagnostic> {
+        diagnostic::validate_with_actions(sources, actions, name, source)
+    }
+
+    // checks like validate, but paths from async sources are awaited
+    #[cfg(feature = "async")]
+    pub async fn validate_async(
+        sources: &Sources,
+        name: &str,
+        source: Option<&str>,
+    ) -> Vec<Diagnostic> {
+        diagnostic::validate_async(sources, name, source).await
+    }
+
+    // checks like validate_with_actions, but paths from async sources are awaited
+    #[cfg(feature = "async")]
+    pub async fn validate_with_actions_async(
+        sources: &Sources,
+        actions: &Actions,
+        name: &str,
+        source: Option<&str>,
+    ) -> Vec<Diagnostic> {
+        diagnostic::validate_with_actions_async(sources, actions, name, source).await
+    }
+
+    // creates like create_from_yaml_str, but paths from async sources are awaited.
+    // the root is built again once every eager path it needs has been fetched
+    #[cfg(feature = "async")]
+    pub async fn create_from_yaml_str_async(
+        sources: Sources,
+        name: String,
+        source: Option<&str>,
+    ) -> Result<Definition, Error> {
+        let name = sources.join(source, None, &name)?;
+        let string = sources.load_async(source, &name).await?;
+        let mut fetched = HashMap::new();
+        loop {
+            let (source, path) = {
+                let state = sources.parse(source, &name, &string)?;
+                let mut machine = Machine::new(sources.clone());
+                machine.set_root_location(source, &name)?;
+                machine.set_fetched(fetched);
+                match state.into_state(&mut machine, None) {
+                    Ok(root) => {
+                        machine.set_root(root);
+                        return Ok(Definition::new(machine));
+                    }
+                    Err(Error::NeedsAsync { source, path }) => {
+                        fetched = machine.take_fetched();
+                        (source, path)
+                    }
+                    Err(error) => return Err(error),
+                }
+            };
+            let string = sources.load_async(source.as_deref(), &path).await?;
+            let source = sources.get_name(source.as_deref())?.to_string();
+            fetched.insert((source, path), string);
+        }
     }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct SerDeContext {
     pub name: String,
     pub value: Option<String>,
     pub r#type: ContextType,
+    // not checked for Options contexts
+    pub rules: Option<Vec<SerDeRule>>,
+    // the context is skipped if the guard does not hold
+    pub when: Option<SerDeGuard>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum ContextType {
     Normal,
-    Options(Vec<SerDeOption>, String, String),
+    Options(SerDeOptionsContext),
+    Integer,
+    Decimal,
+    Boolean,
+    Date,
+    Time,
+    Email,
+    Url,
 }
 
 impl SerDeContext {
     pub fn into_context(
         self,
-        parent_of_options_state: Option<Arc<RwLock<State>>>,
-        how_to_get_string: Vec<fn(String) -> Result<String, Box<dyn StdError>>>,
-        cache: Arc<RwLock<HashMap<String, Arc<RwLock<State>>>>>,
-    ) -> Result<Result<Context, StateError>, Box<dyn StdError>> {
+        machine: &mut Machine,
+        parent_of_options_state: Option<StateId>,
+    ) -> Result<Context, Error> {
+        let state_name = parent_of_options_state
+            .map(|x| machine.get_name(x))
+            .unwrap_or_default();
+        let invalid = |reason: String| Error::invalid(&state_name, Some(&self.name), reason);
         let value = self.value.unwrap_or_default();
+        let when = self
+            .when
+            .map(|x| x.into_guard())
+            .transpose()
+            .map_err(invalid)?;
 
-        match self.r#type {
-            ContextType::Normal => {
-                return Ok(Ok(Context::StateContext(StateContext::new(
-                    self.name, value,
-                ))));
-            }
-            ContextType::Options(options, given_option, given_question) => {
-                let name = match parent_of_options_state.clone() {
-                    Some(parent) => parent.read().get_name(),
+        let kind = match self.r#type {
+            ContextType::Normal => Kind::Text,
+            ContextType::Integer => Kind::Integer,
+            ContextType::Decimal => Kind::Decimal,
+            ContextType::Boolean => Kind::Boolean,
+            ContextType::Date => Kind::Date,
+            ContextType::Time => Kind::Time,
+            ContextType::Email => Kind::Email,
+            ContextType::Url => Kind::Url,
+            ContextType::Options(SerDeOptionsContext {
+                options,
+                other_label: given_option,
+                other_question: given_question,
+            }) => {
+                let name = match parent_of_options_state {
+                    Some(parent) => machine.get_name(parent),
                     None => String::new(),
                 };
                 //create the valid options state
-                let state_for_valid_options: Arc<RwLock<State>> =
-                    Arc::new(RwLock::new(State::OptionsState(OptionsState::new(
-                        name.clone(),
-                        self.name.clone(),
-                        parent_of_options_state.clone(),
-                        vec![],
-                    ))));
+                let state_for_valid_options = machine.push(State::OptionsState(OptionsState::new(
+                    name.clone(),
+                    self.name.clone(),
+                    parent_of_options_state,
+                    vec![],
+                )));
                 //the context will be automatically added to the state
                 //create a context state with only one context
-                if let Some(some_parent_of_options_state) = parent_of_options_state.clone() {
-                    let state_for_context: Arc<RwLock<State>> =
-                        Arc::new(RwLock::new(State::ContextState(ContextState::new(
-                            name.clone(),
-                            self.name.clone(),
-                            Some(state_for_valid_options.clone()),
-                            Some(some_parent_of_options_state.clone()),
-                            vec![Context::StateContext(StateContext::new(
-                                given_question,
-                                String::new(),
-                            ))],
-                            false,
-                        ))));
+                if let Some(some_parent_of_options_state) = parent_of_options_state {
+                    let state_for_context = machine.push(State::ContextState(ContextState::new(
+                        name,
+                        self.name.clone(),
+                        Some(state_for_valid_options),
+                        Some(some_parent_of_options_state),
+                        vec![Context::StateContext(StateContext::new(
+                            given_question,
+                            String::new(),
+                            Kind::Text,
+                            vec![],
+                            None,
+                        ))],
+                        false,
+                    )));
 
                     //create the option that holds the context state
                     let option = StateOption::new(
                         given_option,
-                        Some(state_for_context.clone()),
+                        Some(state_for_context),
                         false,
                         false,
+                        None,
+                        None,
+                        None,
                     );
 
                     //create the valid options
@@ -160,116 +341,673 @@ impl SerDeContext {
                         .into_iter()
                         .map(|x| {
                             x.into_option(
-                                Some(state_for_valid_options.clone()),
-                                parent_of_options_state.clone(),
-                                how_to_get_string.clone(),
-                                cache.clone(),
+                                machine,
+                                Some(state_for_valid_options),
+                                parent_of_options_state,
                             )
                         })
-                        .collect::<Result<Result<Vec<StateOption>, StateError>, Box<dyn StdError>>>(
-                        )??;
+                        .collect::<Result<Vec<StateOption>, Error>>()?;
 
                     //add the option that holds the context state
                     options.push(option);
 
                     //add the options
-                    state_for_valid_options.write().set_options(options);
+                    machine
+                        .get_mut(state_for_valid_options)
+                        .ok_or_else(shared)?
+                        .set_options(options)?;
 
                     //return the OptionsContext
-                    return Ok(Ok(Context::StateOptionsContext(StateOptionsContext::new(
+                    return Ok(Context::StateOptionsContext(StateOptionsContext::new(
                         self.name,
                         value,
-                        state_for_valid_options.clone(),
-                    ))));
+                        state_for_valid_options,
+                        when,
+                    )));
                 }
-                Ok(Err(StateError::BadConstruction))
+                let reason = "Options contexts are only allowed in context states";
+                return Err(invalid(reason.to_string()));
             }
+        };
+
+        //the value given in the definition has to be of the right kind too
+        if let Err(reason) = kind.parse(&value) {
+            return Err(invalid(format!("Bad value: {}", reason)));
         }
+        let rules = self
+            .rules
+            .unwrap_or_default()
+            .into_iter()
+            .map(|x| x.into_rule())
+            .collect::<Result<Vec<Rule>, String>>()
+            .map_err(invalid)?;
+        Ok(Context::StateContext(StateContext::new(
+            self.name, value, kind, rules, when,
+        )))
     }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum SerDeRule {
+    Required,
+    MinLength(usize),
+    MaxLength(usize),
+    Regex(String),
+    IntegerRange(Option<i64> /*min*/, Option<i64> /*max*/),
+    DecimalRange(Option<f64> /*min*/, Option<f64> /*max*/),
+    OneOf(Vec<String>),
+}
+
+impl SerDeRule {
+    // gives the reason if the rule can not be built
+    pub fn into_rule(self) -> Result<Rule, String> {
+        Ok(match self {
+            SerDeRule::Required => Rule::Required,
+            SerDeRule::MinLength(min) => Rule::MinLength(min),
+            SerDeRule::MaxLength(max) => Rule::MaxLength(max),
+            //the whole input has to match, not only a part of it
+            SerDeRule::Regex(regex) => Rule::Regex(
+                Regex::new(&format!("^(?:{})$", regex))
+                    .map_err(|error| format!("Bad regex {}: {}", regex, error))?,
+            ),
+            SerDeRule::IntegerRange(min, max) => Rule::IntegerRange(min, max),
+            SerDeRule::DecimalRange(min, max) => Rule::DecimalRange(min, max),
+            SerDeRule::OneOf(values) => Rule::OneOf(values),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct SerDeOption {
     pub name: String,
     pub submit: Option<bool>,
     pub state: Option<SerDeIntoState>,
     pub reset: Option<bool>,
+    // the option is hidden if the guard does not hold
+    pub when: Option<SerDeGuard>,
+    // the name of the action that runs when the option is chosen
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+    // the state to go to if the action fails
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<SerDeIntoState>,
 }
 
 impl SerDeOption {
     pub fn into_option(
         self,
-        parent: Option<Arc<RwLock<State>>>,
-        backup_state: Option<Arc<RwLock<State>>>,
-        how_to_get_string: Vec<fn(String) -> Result<String, Box<dyn StdError>>>,
-        cache: Arc<RwLock<HashMap<String, Arc<RwLock<State>>>>>,
-    ) -> Result<Result<StateOption, StateError>, Box<dyn StdError>> {
+        machine: &mut Machine,
+        parent: Option<StateId>,
+        backup_state: Option<StateId>,
+    ) -> Result<StateOption, Error> {
         let submit = self.submit.unwrap_or(false);
         let reset = self.reset.unwrap_or(false);
+        let when = self
+            .when
+            .map(|x| x.into_guard())
+            .transpose()
+            .map_err(|reason| {
+                let state = parent.map(|x| machine.get_name(x)).unwrap_or_default();
+                Error::invalid(state, None, format!("Option {}: {}", self.name, reason))
+            })?;
+
+        let state = match self.state {
+            Some(state) => Some(state.into_into_state(machine, parent)?),
+            //the backup state is the state that holds the context of these options
+            None => backup_state,
+        };
+        let failure = self
+            .failure
+            .map(|x| x.into_into_state(machine, parent))
+            .transpose()?;
+        Ok(StateOption::new(
+            self.name,
+            state,
+            submit,
+            reset,
+            when,
+            self.action,
+            failure,
+        ))
+    }
+}
 
-        if let Some(state) = self.state {
-            let state = state.into_into_state(parent, how_to_get_string, cache)??;
-            return Ok(Ok(StateOption::new(self.name, Some(state), submit, reset)));
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum SerDeIntoState {
+    Inline(SerDeState),
+    Path(SerDePath),
+    // only for the next state of a context state
+    When(SerDeGuard, Box<SerDeIntoState>),
+    // only for the next state of a context state.
+    // the first branch whose guard holds is taken, the one without a guard is the default
+    Branches(Vec<SerDeBranch>),
+}
+
+// where a state is loaded from. in yaml either only the path, [path, lazy, source]
+// or a map with these fields, where lazy and source can be left out or null.
+// the source may also be given as loader.
+// without a source the default source is used. a number picks a source by the order
+// the sources were added in, unless a source has that name (see Sources::get_name)
+#[derive(Debug, Serialize)]
+pub struct SerDePath {
+    pub path: String,
+    pub lazy: Option<bool>,
+    pub source: Option<String>,
+}
+
+impl<'de> Deserialize<'de> for SerDePath {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        //sources used to be picked by their index, so a number names a source too.
+        //it is kept as text and only looked up once the sources are known
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum SourceName {
+            Name(String),
+            Index(u64),
         }
 
-        if let Some(state_g) = backup_state {
-            return Ok(Ok(StateOption::new(
-                self.name,
-                Some(state_g.clone()),
-                submit,
-                reset,
-            )));
+        impl From<SourceName> for String {
+            fn from(name: SourceName) -> String {
+                match name {
+                    SourceName::Name(name) => name,
+                    SourceName::Index(index) => index.to_string(),
+                }
+            }
         }
 
-        return Ok(Ok(StateOption::new(self.name, None, submit, reset)));
-        //Ok(Err(StateError::BadConstruction))
+        struct PathVisitor;
+
+        impl<'de> Visitor<'de> for PathVisitor {
+            type Value = SerDePath;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a path or [path, lazy, source]")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<SerDePath, E> {
+                Ok(SerDePath {
+                    path: v.to_string(),
+                    lazy: None,
+                    source: None,
+                })
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<SerDePath, A::Error> {
+                let path: String = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let lazy: Option<bool> = seq.next_element()?.flatten();
+                let source: Option<SourceName> = seq.next_element()?.flatten();
+                if seq.next_element::<de::IgnoredAny>()?.is_some() {
+                    return Err(de::Error::invalid_length(4, &self));
+                }
+                Ok(SerDePath {
+                    path,
+                    lazy,
+                    source: source.map(String::from),
+                })
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<SerDePath, A::Error> {
+                #[derive(Deserialize)]
+                struct Fields {
+                    path: String,
+                    lazy: Option<bool>,
+                    #[serde(alias = "loader")]
+                    source: Option<SourceName>,
+                }
+
+                let fields = Fields::deserialize(MapAccessDeserializer::new(map))?;
+                Ok(SerDePath {
+                    path: fields.path,
+                    lazy: fields.lazy,
+                    source: fields.source.map(String::from),
+                })
+            }
+        }
+
+        deserializer.deserialize_any(PathVisitor)
     }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
-pub enum SerDeIntoState {
-    Inline(SerDeState),
-    Path(
-        String,       /*path to state*/
-        Option<bool>, /*lazy*/
-        usize,        /*which function*/
-    ),
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SerDeBranch {
+    pub when: Option<SerDeGuard>,
+    pub state: SerDeIntoState,
+}
+
+// either the text of an expression or a condition
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(untagged)]
+pub enum SerDeGuard {
+    Expression(String),
+    Condition(Condition),
+}
+
+//untagged enums can not read yaml tags, so a string is an expression and anything else a condition
+impl<'de> Deserialize<'de> for SerDeGuard {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct GuardVisitor;
+
+        impl<'de> Visitor<'de> for GuardVisitor {
+            type Value = SerDeGuard;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an expression or a condition")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<SerDeGuard, E> {
+                Ok(SerDeGuard::Expression(v.to_string()))
+            }
+
+            fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<SerDeGuard, A::Error> {
+                Condition::deserialize(EnumAccessDeserializer::new(data)).map(SerDeGuard::Condition)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<SerDeGuard, A::Error> {
+                Condition::deserialize(MapAccessDeserializer::new(map)).map(SerDeGuard::Condition)
+            }
+        }
+
+        deserializer.deserialize_any(GuardVisitor)
+    }
+}
+
+impl SerDeGuard {
+    // expressions are parsed here, they are type checked once their state is built
+    // gives the reason if the expression can not be parsed
+    pub fn into_guard(self) -> Result<Guard, String> {
+        match self {
+            SerDeGuard::Expression(source) => {
+                let expression = Expression::parse(&source)
+                    .map_err(|reason| format!("Bad expression {}: {}", source, reason))?;
+                Ok(Guard::Expression(expression))
+            }
+            SerDeGuard::Condition(condition) => Ok(Guard::Condition(condition)),
+        }
+    }
 }
 
 impl SerDeIntoState {
     pub fn into_into_state(
         self,
-        parent: Option<Arc<RwLock<State>>>,
-        how_to_get_string: Vec<fn(String) -> Result<String, Box<dyn StdError>>>,
-        cache: Arc<RwLock<HashMap<String, Arc<RwLock<State>>>>>,
-    ) -> Result<Result<Arc<RwLock<State>>, StateError>, Box<dyn StdError>> {
+        machine: &mut Machine,
+        parent: Option<StateId>,
+    ) -> Result<StateId, Error> {
         match self {
-            SerDeIntoState::Inline(state) => {
-                let state = state.into_state(parent, how_to_get_string, cache)??;
-                Ok(Ok(state))
-            }
-            SerDeIntoState::Path(path, lazy, which_function) => {
-                let lazy = lazy.unwrap_or(false);
-                let state_holder = State::StateHolder(StateHolder::new(
+            SerDeIntoState::Inline(state) => state.into_state(machine, parent),
+            SerDeIntoState::Path(path) => {
+                let lazy = path.lazy.unwrap_or(false);
+                //unknown sources and bad paths fail when the definition is loaded, even for lazy paths
+                let joined = machine.join(path.source.as_deref(), &path.path)?;
+                let source = machine.get_sources().get_name(path.source.as_deref())?;
+                let state_holder = machine.push(State::StateHolder(StateHolder::new(
                     parent,
-                    path,
-                    how_to_get_string,
-                    which_function,
-                    lazy,
-                    cache,
-                )?);
-                Ok(Ok(Arc::new(RwLock::new(state_holder))))
+                    joined,
+                    source.to_string(),
+                )));
+                if !lazy {
+                    machine.resolve(state_holder)?;
+                }
+                Ok(state_holder)
+            }
+            SerDeIntoState::When(_, _) | SerDeIntoState::Branches(_) => {
+                let state = parent.map(|x| machine.get_name(x)).unwrap_or_default();
+                let reason = "When and Branches are only allowed as the next state";
+                Err(Error::invalid(state, None, reason))
             }
         }
     }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum StateType {
     Options(Vec<SerDeOption>),
-    Context(
-        Vec<SerDeContext>,           /*context*/
-        bool,                        /*submit*/
-        Option<Box<SerDeIntoState>>, /*next state*/
-    ),
+    Context(SerDeContextState),
+}
+
+// in version 1 written as [contexts, submit, next], without an action
+#[derive(Debug, Serialize)]
+pub struct SerDeContextState {
+    pub contexts: Vec<SerDeContext>,
+    pub submit: bool,
+    pub next: Option<Box<SerDeIntoState>>,
+    // the name of the action that runs once every context is answered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+    // the state to go to if the action fails
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure: Option<Box<SerDeIntoState>>,
+}
+
+impl<'de> Deserialize<'de> for SerDeContextState {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ContextStateVisitor;
+
+        impl<'de> Visitor<'de> for ContextStateVisitor {
+            type Value = SerDeContextState;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "contexts, submit and next or [contexts, submit, next]")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<SerDeContextState, A::Error> {
+                let contexts = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let submit = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                let next = seq.next_element()?.flatten();
+                if seq.next_element::<de::IgnoredAny>()?.is_some() {
+                    return Err(de::Error::invalid_length(4, &self));
+                }
+                Ok(SerDeContextState {
+                    contexts,
+                    submit,
+                    next,
+                    action: None,
+                    failure: None,
+                })
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<SerDeContextState, A::Error> {
+                #[derive(Deserialize)]
+                struct Fields {
+                    contexts: Vec<SerDeContext>,
+                    #[serde(default)]
+                    submit: bool,
+                    next: Option<Box<SerDeIntoState>>,
+                    action: Option<String>,
+                    failure: Option<Box<SerDeIntoState>>,
+                }
+
+                let fields = Fields::deserialize(MapAccessDeserializer::new(map))?;
+                Ok(SerDeContextState {
+                    contexts: fields.contexts,
+                    submit: fields.submit,
+                    next: fields.next,
+                    action: fields.action,
+                    failure: fields.failure,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(ContextStateVisitor)
+    }
+}
+
+// in version 1 written as [options, other_label, other_question]
+#[derive(Debug, Serialize)]
+pub struct SerDeOptionsContext {
+    pub options: Vec<SerDeOption>,
+    // the label of the option that lets the user enter something else
+    pub other_label: String,
+    // the question that is asked when that option is chosen
+    pub other_question: String,
+}
+
+impl<'de> Deserialize<'de> for SerDeOptionsContext {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct OptionsContextVisitor;
+
+        impl<'de> Visitor<'de> for OptionsContextVisitor {
+            type Value = SerDeOptionsContext;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(
+                    f,
+                    "options, other_label and other_question or [options, other_label, other_question]"
+                )
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<SerDeOptionsContext, A::Error> {
+                let options = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let other_label = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                let other_question = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+                if seq.next_element::<de::IgnoredAny>()?.is_some() {
+                    return Err(de::Error::invalid_length(4, &self));
+                }
+                Ok(SerDeOptionsContext {
+                    options,
+                    other_label,
+                    other_question,
+                })
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<SerDeOptionsContext, A::Error> {
+                #[derive(Deserialize)]
+                struct Fields {
+                    options: Vec<SerDeOption>,
+                    other_label: String,
+                    other_question: String,
+                }
+
+                let fields = Fields::deserialize(MapAccessDeserializer::new(map))?;
+                Ok(SerDeOptionsContext {
+                    options: fields.options,
+                    other_label: fields.other_label,
+                    other_question: fields.other_question,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(OptionsContextVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::LoadError;
+
+    const VERSION_1: &str = r#"
+name: Main
+description: Main description
+type: !Options
+- name: Option0
+  submit: false
+  state: !Path
+  - child.yaml
+  - false
+  - 1
+- name: Option1
+  submit: false
+  state: !Inline
+    name: Child1
+    description: I am Child1
+    type: !Context
+    - - name: How are you?
+        type: !Normal
+      - name: Where?
+        type: !Options
+        - - name: Here
+          - name: There
+        - Else where
+        - Where could it be?
+    - false
+    - null
+"#;
+
+    const VERSION_2: &str = r#"
+version: 2
+name: Main
+description: Main description
+type: !Context
+  contexts:
+  - name: Where?
+    type: !Options
+      options:
+      - name: Here
+      - name: There
+      other_label: Else where
+      other_question: Where could it be?
+  action: remember
+  failure: !Path
+    path: failed.yaml
+    lazy: true
+    loader: api
+  next: !Path child.yaml
+"#;
+
+    fn parse(string: &str) -> SerDeState {
+        serde_yaml::from_str(string).unwrap()
+    }
+
+    // reading what was written gives the same document again
+    fn round_trip(state: &SerDeState) -> SerDeState {
+        let written = serde_yaml::to_string(state).unwrap();
+        let read = parse(&written);
+        assert_eq!(written, serde_yaml::to_string(&read).unwrap());
+        read
+    }
+
+    fn contexts(state: &SerDeState) -> &SerDeContextState {
+        match &state.r#type {
+            StateType::Context(context_state) => context_state,
+            StateType::Options(_) => panic!("{} is not a context state", state.name),
+        }
+    }
+
+    fn path(into: Option<&SerDeIntoState>) -> &SerDePath {
+        match into {
+            Some(SerDeIntoState::Path(path)) => path,
+            other => panic!("{:?} is not a path", other),
+        }
+    }
+
+    fn options_context(context: &SerDeContext) -> &SerDeOptionsContext {
+        match &context.r#type {
+            ContextType::Options(options_context) => options_context,
+            other => panic!("{:?} is not an options context", other),
+        }
+    }
+
+    fn check_version_1(state: &SerDeState) {
+        let options = match &state.r#type {
+            StateType::Options(options) => options,
+            StateType::Context(_) => panic!("Main is not an options state"),
+        };
+        let child = path(options[0].state.as_ref());
+        assert_eq!(child.path, "child.yaml");
+        assert_eq!(child.lazy, Some(false));
+        assert_eq!(child.source.as_deref(), Some("1"));
+
+        let child1 = match &options[1].state {
+            Some(SerDeIntoState::Inline(child1)) => child1,
+            other => panic!("{:?} is not inline", other),
+        };
+        let context_state = contexts(child1);
+        assert!(!context_state.submit);
+        assert!(context_state.next.is_none());
+        assert!(context_state.action.is_none());
+        let where_ = options_context(&context_state.contexts[1]);
+        assert_eq!(where_.options.len(), 2);
+        assert_eq!(where_.other_label, "Else where");
+        assert_eq!(where_.other_question, "Where could it be?");
+    }
+
+    fn check_version_2(state: &SerDeState) {
+        assert_eq!(state.version, Some(VERSION));
+        let context_state = contexts(state);
+        assert_eq!(context_state.action.as_deref(), Some("remember"));
+        let failure = path(context_state.failure.as_deref());
+        assert_eq!(failure.path, "failed.yaml");
+        assert_eq!(failure.lazy, Some(true));
+        assert_eq!(failure.source.as_deref(), Some("api"));
+        let next = path(context_state.next.as_deref());
+        assert_eq!(next.path, "child.yaml");
+        assert!(next.lazy.is_none() && next.source.is_none());
+        let where_ = options_context(&context_state.contexts[0]);
+        assert_eq!(where_.options[1].name, "There");
+        assert_eq!(where_.other_label, "Else where");
+    }
+
+    #[test]
+    fn version_1_round_trip() {
+        let state = parse(VERSION_1);
+        check_version_1(&state);
+        //written with the named fields of version 2, read back the same
+        let written = serde_yaml::to_string(&state).unwrap();
+        assert!(written.contains("other_question:"));
+        check_version_1(&round_trip(&state));
+    }
+
+    #[test]
+    fn version_2_round_trip() {
+        let state = parse(VERSION_2);
+        check_version_2(&state);
+        check_version_2(&round_trip(&state));
+    }
+
+    #[test]
+    fn path_forms() {
+        let read = |string: &str| serde_yaml::from_str::<SerDePath>(string).unwrap();
+        let only_path = read("child.yaml");
+        assert!(only_path.lazy.is_none() && only_path.source.is_none());
+        let list = read("[child.yaml, null, api]");
+        assert!(list.lazy.is_none());
+        assert_eq!(list.source.as_deref(), Some("api"));
+        let map = read("{path: child.yaml, source: 0}");
+        assert_eq!(map.source.as_deref(), Some("0"));
+        assert!(serde_yaml::from_str::<SerDePath>("[child.yaml, true, api, extra]").is_err());
+        assert!(serde_yaml::from_str::<SerDePath>("{lazy: true}").is_err());
+    }
+
+    // a source that gives the version 1 document for main.yaml and a state with the name for
+    // any other path
+    fn source(name: &'static str) -> impl Fn(&str) -> Result<String, LoadError> {
+        move |path: &str| {
+            match path {
+            "main.yaml" => Ok(String::from(VERSION_1)),
+            _ => Ok(format!(
+                "name: {}\ndescription: d\ntype: !Context\n- - name: x\n    type: !Normal\n- false\n- null\n",
+                name
+            )),
+        }
+        }
+    }
+
+    #[test]
+    fn version_1_sources_by_index() {
+        let mut sources = Sources::new();
+        sources.add("local", source("FromLocal"));
+        sources.add("api", source("FromApi"));
+        sources.set_default("local");
+
+        let definition =
+            SerDeState::create_from_yaml_str(sources.clone(), String::from("main.yaml"), None)
+                .unwrap();
+        let machine = definition.get_machine();
+        let child = machine.find_holder(Some("api"), "child.yaml").unwrap();
+        let child = machine.get_loaded(child).unwrap();
+        assert_eq!(machine.get_name(child), "FromApi");
+
+        //a source named like the number is taken over the position
+        sources.add("1", source("FromOne"));
+        let definition =
+            SerDeState::create_from_yaml_str(sources, String::from("main.yaml"), None).unwrap();
+        let machine = definition.get_machine();
+        let child = machine.find_holder(Some("1"), "child.yaml").unwrap();
+        let child = machine.get_loaded(child).unwrap();
+        assert_eq!(machine.get_name(child), "FromOne");
+    }
 }
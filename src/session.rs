@@ -0,0 +1,1451 @@
+use crate::{
+    collection::{Collection, ContextLikeCollection},
+    condition::Guard,
+    context::Context,
+    definition::Definition,
+    error::Error,
+    machine::{Machine, StateId},
+    observer::{Event, Observer},
+    snapshot::{ProgressSnapshot, SessionSnapshot, StateKey},
+    state::State,
+    status::{InputStatus, Output, OutputStatus, StatusLike},
+    template,
+    trace::event,
+    value::Value,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+// the input that makes a session go one step back instead of being passed to the state
+pub const BACK: &str = "back";
+
+#[derive(Debug)]
+pub enum StepResult {
+    // the input was accepted and the current state has more to ask
+    Prompt(Output),
+    // the input was accepted and the session moved to another state
+    Transitioned(Output),
+    // the flow is done, these are the collected answers
+    Submitted(Vec<Collection>),
+    // the input was not recognized, the current prompt is given again with the reason
+    Rejected(Output, Option<String>),
+}
+
+// what a user did in a state
+#[derive(Clone, Default)]
+struct Progress {
+    // the current context of a ContextState or the chosen option of an OptionsState
+    index: usize,
+    // set when the user went back past the first context of a ContextState
+    go_back: bool,
+    // the values entered for the contexts of a ContextState, by the index of the context
+    values: HashMap<usize, String>,
+}
+
+// drives a flow from its root until it submits.
+// the definition is shared, a session only holds where the user is and what they answered.
+// frontends only have to show the output and pass the user's input to step
+#[derive(Clone)]
+pub struct Session {
+    definition: Arc<Definition>,
+    machine: Arc<Machine>,
+    current: StateId,
+    // the states the session came through from the root to the current one.
+    // the states of a path are shared by every visit of it, so their parents may be the
+    // ones of another visit. collect and back follow the trail instead, see get_parent
+    trail: Vec<StateId>,
+    progress: HashMap<StateId, Progress>,
+    submitted: bool,
+    // set once the session has entered its root
+    started: bool,
+    // the observers of this session, after the ones of the definition
+    observers: Vec<Arc<dyn Observer>>,
+    // what happened in the current step, given to the observers once it is done
+    events: Vec<Event>,
+    // why an action failed while the session moved on by itself, see finish
+    rejected: Option<String>,
+    // the outcomes of the actions an async step ran before it had to wait for a state.
+    // the step runs again from the start and takes them instead of running the actions again
+    #[cfg(feature = "async")]
+    outcomes: Vec<Option<String>>,
+    #[cfg(feature = "async")]
+    replayed: usize,
+    // set while an async step runs. states that have not been loaded are not loaded
+    // then, the step stops with NeedsAsync instead so the state can be awaited
+    #[cfg(feature = "async")]
+    deferred: bool,
+}
+
+impl Session {
+    pub fn new(definition: Arc<Definition>) -> Session {
+        let machine = definition.get_machine();
+        Session {
+            current: machine.get_root(),
+            trail: vec![machine.get_root()],
+            machine,
+            definition,
+            progress: HashMap::new(),
+            submitted: false,
+            started: false,
+            observers: vec![],
+            events: vec![],
+            rejected: None,
+            #[cfg(feature = "async")]
+            outcomes: vec![],
+            #[cfg(feature = "async")]
+            replayed: 0,
+            #[cfg(feature = "async")]
+            deferred: false,
+        }
+    }
+
+    // continues a session from a snapshot taken from the same definition.
+    // the paths the snapshot went through are loaded again if needed.
+    // gives SnapshotMismatch if the definition does not have the states of the snapshot anymore,
+    // or if their contexts or options changed so the answers do not fit them
+    pub fn restore(
+        definition: Arc<Definition>,
+        snapshot: &SessionSnapshot,
+    ) -> Result<Session, Error> {
+        let mut session = Session::new(definition);
+        let keys: Vec<&StateKey> = snapshot
+            .progress
+            .iter()
+            .map(|x| &x.state)
+            .chain(&snapshot.trail)
+            .chain(std::iter::once(&snapshot.state))
+            .collect();
+
+        //a path might only be reachable through another lazy path, so load until nothing is left
+        loop {
+            let holders: Vec<StateId> = keys
+                .iter()
+                .filter(|x| session.machine.find_key(x).is_none())
+                .filter_map(|x| {
+                    session
+                        .machine
+                        .find_holder(x.source.as_deref(), x.path.as_ref()?)
+                })
+                .filter(|x| session.machine.get_loaded(*x).is_none())
+                .collect();
+            if holders.is_empty() {
+                break;
+            }
+            for holder in holders {
+                session.resolve(holder)?;
+            }
+        }
+
+        session.current = match session.machine.find_key(&snapshot.state) {
+            Some(state) => state,
+            None => return Err(Error::SnapshotMismatch),
+        };
+        session.trail = snapshot
+            .trail
+            .iter()
+            .map(|x| session.machine.find_key(x).ok_or(Error::SnapshotMismatch))
+            .collect::<Result<Vec<StateId>, Error>>()?;
+        if session.trail.last() != Some(&session.current) {
+            session.trail.push(session.current);
+        }
+        //snapshots taken before the trail was kept go by the parents of the states
+        if snapshot.trail.is_empty() {
+            while let Some(parent) = session.machine.state(session.trail[0]).get_parent() {
+                if session.trail.contains(&parent) {
+                    break;
+                }
+                session.trail.insert(0, parent);
+            }
+        }
+        session.submitted = snapshot.submitted;
+        session.started = true;
+
+        for progress in &snapshot.progress {
+            let id = match session.machine.find_key(&progress.state) {
+                Some(id) => id,
+                None => return Err(Error::SnapshotMismatch),
+            };
+            //the contexts or options of the state changed if its fingerprint did,
+            //and what was entered has to be valid for them still
+            let fits = progress
+                .fingerprint
+                .is_none_or(|x| session.machine.get_fingerprint(id) == Some(x))
+                && match session.machine.state(id) {
+                    State::OptionsState(state) => {
+                        progress.index < state.options.len() && progress.values.is_empty()
+                    }
+                    State::ContextState(state) => {
+                        progress.index <= state.contexts.len()
+                            && progress.values.iter().all(|(index, value)| {
+                                match state.contexts.get(*index) {
+                                    Some(context) => context.validate(value).is_none(),
+                                    None => false,
+                                }
+                            })
+                    }
+                    _ => false,
+                };
+            if !fits {
+                return Err(Error::SnapshotMismatch);
+            }
+            session.progress.insert(
+                id,
+                Progress {
+                    index: progress.index,
+                    go_back: progress.go_back,
+                    values: progress.values.clone().into_iter().collect(),
+                },
+            );
+        }
+
+        Ok(session)
+    }
+
+    // saves where the user is and what they answered, see restore
+    pub fn snapshot(&self) -> SessionSnapshot {
+        let mut ids: Vec<&StateId> = self.progress.keys().collect();
+        ids.sort_by_key(|x| x.index());
+        let progress = ids
+            .into_iter()
+            .map(|id| {
+                let progress = &self.progress[id];
+                ProgressSnapshot {
+                    state: self.machine.key(*id),
+                    index: progress.index,
+                    go_back: progress.go_back,
+                    values: progress.values.clone().into_iter().collect(),
+                    fingerprint: self.machine.get_fingerprint(*id),
+                }
+            })
+            .collect();
+
+        SessionSnapshot {
+            state: self.machine.key(self.current),
+            submitted: self.submitted,
+            progress,
+            trail: self.trail.iter().map(|x| self.machine.key(*x)).collect(),
+        }
+    }
+
+    pub fn get_definition(&self) -> Arc<Definition> {
+        self.definition.clone()
+    }
+
+    pub fn get_machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn get_state(&self) -> StateId {
+        self.current
+    }
+
+    // the states the session came through from the root, the current one last
+    pub fn get_trail(&self) -> &[StateId] {
+        &self.trail
+    }
+
+    // the state the session came to a state from, or the parent of the state if the
+    // session did not come through it
+    pub fn get_parent(&self, id: StateId) -> Option<StateId> {
+        match self.trail.iter().position(|x| *x == id) {
+            Some(0) => None,
+            Some(index) => Some(self.trail[index - 1]),
+            None => self.machine.get(id)?.get_parent(),
+        }
+    }
+
+    pub fn is_submitted(&self) -> bool {
+        self.submitted
+    }
+
+    pub fn add_observer(&mut self, observer: impl Observer + 'static) {
+        self.observers.push(Arc::new(observer));
+    }
+
+    // the current context of a ContextState or the chosen option of an OptionsState
+    pub fn get_index(&self, id: StateId) -> usize {
+        self.progress.get(&id).map(|x| x.index).unwrap_or(0)
+    }
+
+    // the value of a context of a ContextState, either entered by the user or given by the definition
+    pub fn get_value(&self, id: StateId, context: usize) -> Option<String> {
+        if let Some(value) = self.progress.get(&id).and_then(|x| x.values.get(&context)) {
+            return Some(value.clone());
+        }
+        match self.machine.get(id) {
+            Some(State::ContextState(state)) => state.contexts.get(context).map(|x| x.get_value()),
+            _ => None,
+        }
+    }
+
+    // the answer the user gave for a context or an OptionsState with the name.
+    // looks at the contexts of the state before the given index, then at its parents
+    pub fn get_answer(&self, id: StateId, before: usize, name: &str) -> Option<Value> {
+        let mut current = Some(id);
+        let mut before = before;
+        while let Some(id) = current {
+            match self.machine.get(id) {
+                Some(State::ContextState(state)) => {
+                    let answer = state
+                        .contexts
+                        .iter()
+                        .enumerate()
+                        .take(before)
+                        .rev()
+                        .filter(|(_, context)| context.get_name() == name)
+                        .find_map(|(index, _)| self.get_context_answer(id, index));
+                    if answer.is_some() {
+                        return answer;
+                    }
+                    current = state.parent;
+                }
+                Some(State::OptionsState(state)) => {
+                    //options of a context are answered by the context
+                    if state.name == name && !self.machine.is_context_options(id) {
+                        if let Some(answer) = self.get_option_answer(id) {
+                            return Some(answer);
+                        }
+                    }
+                    current = state.parent;
+                }
+                _ => current = None,
+            }
+            before = usize::MAX;
+        }
+        None
+    }
+
+    // the value entered for a context or the option chosen for it, nothing if it was not answered
+    fn get_context_answer(&self, id: StateId, index: usize) -> Option<Value> {
+        let context = match self.machine.state(id) {
+            State::ContextState(state) => state.contexts.get(index)?,
+            _ => return None,
+        };
+        match context {
+            Context::StateContext(state_context) => {
+                let value = self.progress.get(&id)?.values.get(&index)?;
+                Some(state_context.parse(value))
+            }
+            Context::StateOptionsContext(state_options_context) => {
+                self.get_option_answer(state_options_context.state)
+            }
+        }
+    }
+
+    // the option chosen in an OptionsState. the last option of the options of a context
+    // gives what was entered for it instead
+    fn get_option_answer(&self, id: StateId) -> Option<Value> {
+        let index = self.progress.get(&id)?.index;
+        let (option, len) = match self.machine.state(id) {
+            State::OptionsState(state) => (state.options.get(index)?, state.options.len()),
+            _ => return None,
+        };
+        if index == len - 1 && self.machine.is_context_options(id) {
+            if let Some(value) = option
+                .get_state()
+                .and_then(|x| self.progress.get(&x))
+                .and_then(|x| x.values.get(&0))
+            {
+                return Some(Value::Text(value.clone()));
+            }
+        }
+        Some(Value::Text(option.get_name()))
+    }
+
+    // checks a guard against the answers given before the context at the index
+    fn check(&self, guard: Option<&Guard>, id: StateId, before: usize) -> bool {
+        match guard {
+            Some(guard) => guard.evaluate(&|name| self.get_answer(id, before, name)),
+            None => true,
+        }
+    }
+
+    // the options of an OptionsState that are listed, by their index
+    fn visible_options(&self, id: StateId) -> Vec<usize> {
+        match self.machine.state(id) {
+            State::OptionsState(state) => (0..state.options.len())
+                .filter(|x| self.check(state.options[*x].get_when(), id, usize::MAX))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    // the visible options of an OptionsState with the labels they are shown with
+    fn visible_labels(&self, id: StateId) -> Vec<(usize, String)> {
+        let visible = self.visible_options(id);
+        match self.machine.state(id) {
+            State::OptionsState(state) => visible
+                .into_iter()
+                .map(|x| (x, self.render(&state.options[x].get_name(), id, usize::MAX)))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    // fills the placeholders of a text with the answers given before the context at the index
+    fn render(&self, text: &str, id: StateId, before: usize) -> String {
+        template::render(text, &|name| self.get_answer(id, before, name))
+    }
+
+    fn is_context_active(&self, id: StateId, index: usize) -> bool {
+        match self.machine.state(id) {
+            State::ContextState(state) => match state.contexts.get(index) {
+                Some(context) => self.check(context.get_when(), id, index),
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    // the active context before the index, if there is one
+    fn previous_active(&self, id: StateId, index: usize) -> Option<usize> {
+        (0..index).rev().find(|x| self.is_context_active(id, *x))
+    }
+
+    // forgets the answer of a context that is skipped,
+    // so later conditions do not see what was entered before it was skipped
+    fn forget(&mut self, id: StateId, index: usize) {
+        let options_state = match self.machine.state(id) {
+            State::ContextState(state) => state.contexts.get(index).and_then(|x| x.output()),
+            _ => None,
+        };
+        if let Some(progress) = self.progress.get_mut(&id) {
+            progress.values.remove(&index);
+        }
+        if let Some(options_state) = options_state {
+            self.progress.remove(&options_state);
+        }
+    }
+
+    // gives the current prompt without any input, e.g. the first one.
+    // may already submit if the flow needs no input
+    pub fn prompt(&mut self) -> Result<StepResult, Error> {
+        if self.submitted {
+            return Err(Error::Misuse(String::from("Session already submitted")));
+        }
+
+        self.start();
+        let result = self.next_prompt(false);
+        self.finish(result)
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(state = %self.current))
+    )]
+    pub fn step(&mut self, input: String) -> Result<StepResult, Error> {
+        if self.submitted {
+            return Err(Error::Misuse(String::from("Session already submitted")));
+        }
+
+        self.start();
+        let result = self.advance(input);
+        self.finish(result)
+    }
+
+    // an action that failed while the session moved on by itself rejects the input it
+    // was given, e.g. when the last context of a state is an options context
+    fn finish(&mut self, result: Result<StepResult, Error>) -> Result<StepResult, Error> {
+        self.notify();
+        match (result, self.rejected.take()) {
+            (Ok(StepResult::Prompt(output)), Some(reason))
+            | (Ok(StepResult::Transitioned(output)), Some(reason)) => {
+                Ok(StepResult::Rejected(output, Some(reason)))
+            }
+            (result, _) => result,
+        }
+    }
+
+    fn advance(&mut self, input: String) -> Result<StepResult, Error> {
+        let state = self.current;
+        let input_status = if input == BACK {
+            event!(DEBUG, "back");
+            self.events.push(Event::Back(state));
+            self.back(state)?
+        } else {
+            self.input(state, input.clone())?
+        };
+
+        if !input_status.input_recognized {
+            event!(DEBUG, reason = ?input_status.reason, "input rejected");
+            let reason = input_status.reason.clone().unwrap_or_default();
+            self.events.push(Event::Input(state, input, Some(reason)));
+            return match self.next_prompt(false)? {
+                StepResult::Prompt(output) => Ok(StepResult::Rejected(output, input_status.reason)),
+                result => Ok(result),
+            };
+        }
+
+        event!(DEBUG, "input accepted");
+        if input != BACK {
+            self.events.push(Event::Input(state, input, None));
+        }
+        if input_status.state_changed {
+            if let Some(state) = input_status.state {
+                self.move_to(state);
+            }
+            if input_status.submit {
+                return self.submit();
+            }
+            return self.next_prompt(true);
+        }
+
+        self.next_prompt(false)
+    }
+
+    // gives the current prompt like prompt, but states from async sources are awaited
+    #[cfg(feature = "async")]
+    pub async fn prompt_async(&mut self) -> Result<StepResult, Error> {
+        self.run_async(|session| session.prompt()).await
+    }
+
+    // steps like step, but states from async sources are awaited
+    #[cfg(feature = "async")]
+    pub async fn step_async(&mut self, input: String) -> Result<StepResult, Error> {
+        self.run_async(|session| session.step(input.clone())).await
+    }
+
+    // runs a step on a copy of the session. if the step reaches a state that has not been
+    // loaded, the state is loaded without blocking and the step runs again from the start.
+    // the copy only replaces the session once the step is done
+    #[cfg(feature = "async")]
+    async fn run_async(
+        &mut self,
+        run: impl Fn(&mut Session) -> Result<StepResult, Error>,
+    ) -> Result<StepResult, Error> {
+        let mut outcomes = vec![];
+        loop {
+            let holder = {
+                let mut session = self.clone();
+                session.deferred = true;
+                session.outcomes = outcomes;
+                session.replayed = 0;
+                match run(&mut session) {
+                    Ok(result) => {
+                        session.deferred = false;
+                        session.outcomes = vec![];
+                        *self = session;
+                        self.notify();
+                        return Ok(result);
+                    }
+                    Err(Error::NeedsAsync { path, source }) => {
+                        outcomes = std::mem::take(&mut session.outcomes);
+                        match self
+                            .definition
+                            .get_machine()
+                            .find_holder(source.as_deref(), &path)
+                        {
+                            Some(holder) => holder,
+                            None => {
+                                return Err(Error::Misuse(format!(
+                                    "{} is not a path of the definition",
+                                    path
+                                )))
+                            }
+                        }
+                    }
+                    Err(error) => return Err(error),
+                }
+            };
+            self.machine = self.definition.load_async(holder).await?;
+        }
+    }
+
+    // follows the states given by output until one of them has something to show.
+    // a state that is passed twice without showing anything would be passed forever,
+    // e.g. one whose contexts are all skipped and that leads back to itself
+    fn next_prompt(&mut self, mut transitioned: bool) -> Result<StepResult, Error> {
+        let mut passed = HashSet::new();
+        loop {
+            if !passed.insert(self.current) {
+                return Err(Error::invalid(
+                    self.machine.get_name(self.current),
+                    None,
+                    "The flow leads back to the state without asking anything",
+                ));
+            }
+            let output_status = self.output(self.current)?;
+
+            if output_status.state_changed {
+                if let Some(state) = output_status.state {
+                    transitioned = transitioned || self.current != state;
+                    self.move_to(state);
+                    continue;
+                }
+                if output_status.submit {
+                    return self.submit();
+                }
+            }
+
+            let output = output_status.output.unwrap_or_default();
+            if transitioned {
+                return Ok(StepResult::Transitioned(output));
+            }
+            return Ok(StepResult::Prompt(output));
+        }
+    }
+
+    // the root is entered with the first prompt or step
+    fn start(&mut self) {
+        if !self.started {
+            self.started = true;
+            self.events.push(Event::Enter(self.current));
+        }
+    }
+
+    // gives what happened in the step to the observers of the definition and the session.
+    // a deferred step keeps it, it only happened once the step is done
+    fn notify(&mut self) {
+        #[cfg(feature = "async")]
+        if self.deferred {
+            return;
+        }
+        let events = std::mem::take(&mut self.events);
+        let definition = self.definition.clone();
+        for event in &events {
+            for observer in definition.get_observers().iter().chain(&self.observers) {
+                event.notify(observer.as_ref(), self);
+            }
+        }
+    }
+
+    // going to a state the session already came through goes back to it
+    fn move_to(&mut self, state: StateId) {
+        match self.trail.iter().position(|x| *x == state) {
+            Some(index) => self.trail.truncate(index + 1),
+            None => self.trail.push(state),
+        }
+        if self.current != state {
+            self.events.push(Event::Exit(self.current));
+            self.events.push(Event::Enter(state));
+            event!(
+                DEBUG,
+                from = %self.current,
+                to = %state,
+                name = %self.machine.get_name(state),
+                "transition"
+            );
+        }
+        self.current = state;
+    }
+
+    fn submit(&mut self) -> Result<StepResult, Error> {
+        let collections = self.collect(self.current)?;
+        self.submitted = true;
+        self.events.push(Event::Submit(collections.clone()));
+        event!(INFO, state = %self.machine.get_name(self.current), "submitted");
+        Ok(StepResult::Submitted(collections))
+    }
+
+    fn progress_mut(&mut self, id: StateId) -> &mut Progress {
+        self.progress.entry(id).or_default()
+    }
+
+    // gives the state a StateHolder stands for, loading it through the definition if needed.
+    // other states stand for themselves
+    pub fn resolve(&mut self, id: StateId) -> Result<StateId, Error> {
+        if !self.machine.contains(id) {
+            return Err(Error::Misuse(format!("State {} is not in the machine", id)));
+        }
+        if let Some(state) = self.machine.get_loaded(id) {
+            return Ok(state);
+        }
+        #[cfg(feature = "async")]
+        if self.deferred && self.definition.get_machine().get_loaded(id).is_none() {
+            return Err(self.needs_async(id));
+        }
+        self.machine = self.definition.load(id)?;
+        self.machine
+            .get_loaded(id)
+            .ok_or_else(|| self.not_loaded(id))
+    }
+
+    // a StateHolder that is still not loaded after loading it
+    fn not_loaded(&self, id: StateId) -> Error {
+        Error::Misuse(format!("{} could not be loaded", self.machine.get_name(id)))
+    }
+
+    // the error a deferred step stops with, naming the path of the StateHolder
+    #[cfg(feature = "async")]
+    fn needs_async(&self, id: StateId) -> Error {
+        match self.machine.state(id) {
+            State::StateHolder(holder) => Error::NeedsAsync {
+                path: holder.path.clone(),
+                source: Some(holder.source.clone()),
+            },
+            _ => self.not_loaded(id),
+        }
+    }
+
+    // the answers of a state could not be collected, e.g. an options context that is not
+    // backed by an OptionsState or an option that was never chosen
+    fn not_collectable(&self, id: StateId) -> Error {
+        Error::Misuse(format!(
+            "The answers of state {} could not be collected",
+            self.machine.get_name(id)
+        ))
+    }
+
+    // follows a transition to a state.
+    // entering a path (a StateHolder) always starts a new visit of it: what was entered
+    // in the states loaded from that path during an earlier visit is forgotten, so loops
+    // between paths never reuse old answers or positions.
+    // the states themselves are loaded only once and shared, the trail keeps which visit
+    // they belong to
+    fn enter(&mut self, id: StateId) -> Result<StateId, Error> {
+        let state = self.resolve(id)?;
+        if self.machine.get_loaded_path(id).is_some() {
+            let machine = self.machine.clone();
+            self.progress
+                .retain(|x, _| !machine.is_same_document(*x, state));
+            self.trail.retain(|x| !machine.is_same_document(*x, state));
+        }
+        Ok(state)
+    }
+
+    //called from an OptionsState that has been created through a Context.
+    //goes back over the given number of active contexts
+    pub fn decrease_index(&mut self, id: StateId, amount: usize) {
+        if let Some(State::ContextState(_)) = self.machine.get(id) {
+            let mut index = Some(self.get_index(id));
+            for _ in 0..amount {
+                index = index.and_then(|x| self.previous_active(id, x));
+            }
+            let progress = self.progress_mut(id);
+            match index {
+                Some(index) => progress.index = index,
+                None => {
+                    progress.go_back = true;
+                    progress.index = 0;
+                }
+            }
+        }
+    }
+
+    pub fn reset_index(&mut self, id: StateId) {
+        //Could reset index of options state. Reset index of the state of every option!
+        if let Some(State::ContextState(state)) = self.machine.get(id) {
+            let next_states = state.get_next_states();
+            self.progress_mut(id).index = 0;
+            for next in next_states {
+                self.reset_index(next);
+            }
+        }
+    }
+
+    pub fn input(&mut self, id: StateId, input: String) -> Result<InputStatus, Error> {
+        let id = self.resolve(id)?;
+        match self.machine.state(id) {
+            State::OptionsState(_) => self.options_input(id, input),
+            State::ContextState(_) => self.context_input(id, input),
+            State::StateHolder(_) => Err(self.not_loaded(id)),
+        }
+    }
+
+    pub fn output(&mut self, id: StateId) -> Result<OutputStatus, Error> {
+        let id = self.resolve(id)?;
+        match self.machine.state(id) {
+            State::OptionsState(_) => self.options_output(id),
+            State::ContextState(_) => self.context_output(id),
+            State::StateHolder(_) => Err(self.not_loaded(id)),
+        }
+    }
+
+    pub fn back(&mut self, id: StateId) -> Result<InputStatus, Error> {
+        let id = self.resolve(id)?;
+        match self.machine.state(id) {
+            State::OptionsState(_) => Ok(self.options_back(id)),
+            State::ContextState(_) => Ok(self.context_back(id)),
+            State::StateHolder(_) => Err(self.not_loaded(id)),
+        }
+    }
+
+    pub fn collect(&mut self, id: StateId) -> Result<Vec<Collection>, Error> {
+        let id = self.resolve(id)?;
+        match self.machine.state(id) {
+            State::OptionsState(_) => self.options_collect(id),
+            State::ContextState(_) => self.context_collect(id),
+            State::StateHolder(_) => Err(self.not_loaded(id)),
+        }
+    }
+
+    // the state an option leads to
+    fn option_state(&mut self, id: StateId, option: usize) -> Result<Option<StateId>, Error> {
+        let state = match self.machine.state(id) {
+            State::OptionsState(state) => state.options.get(option).and_then(|x| x.get_state()),
+            _ => None,
+        };
+        match state {
+            Some(state) => Ok(Some(self.resolve(state)?)),
+            None => Ok(None),
+        }
+    }
+
+    // gives the reason if the action of the state failed and there is no failure state.
+    // the state then goes back to its last active context, or stays done if none is active.
+    // the step is rejected either way, see finish
+    fn on_highest_index(
+        &mut self,
+        id: StateId,
+        status: &mut impl StatusLike,
+    ) -> Result<Option<String>, Error> {
+        let (submit, next, action, failure) = match self.machine.state(id) {
+            State::ContextState(state) => (
+                state.submit,
+                state
+                    .branches
+                    .iter()
+                    .find(|x| self.check(Some(&x.when), id, usize::MAX))
+                    .map(|x| x.state)
+                    .or(state.next),
+                state.action.clone(),
+                state.failure,
+            ),
+            _ => (false, None, None, None),
+        };
+
+        if let Some(action) = action {
+            if let Some(reason) = self.run_action(id, &action)? {
+                if let Some(failure) = failure {
+                    let failure = self.enter(failure)?;
+                    status.set_state_changed(true);
+                    status.set_submit(false);
+                    status.set_state(Some(failure));
+                    return Ok(None);
+                }
+                let len = self.machine.state(id).get_contexts().map_or(0, |x| x.len());
+                if let Some(last) = self.previous_active(id, len) {
+                    self.progress_mut(id).index = last;
+                }
+                self.rejected = Some(reason.clone());
+                return Ok(Some(reason));
+            }
+        }
+
+        status.set_state_changed(true);
+        status.set_submit(submit);
+
+        if let Some(next) = next {
+            let next = self.enter(next)?;
+            status.set_state(Some(next));
+        } else {
+            status.set_submit(true);
+        }
+        Ok(None)
+    }
+
+    // runs the action with the name on the answers collected up to the state.
+    // gives the reason if it failed
+    fn run_action(&mut self, id: StateId, name: &str) -> Result<Option<String>, Error> {
+        #[cfg(feature = "async")]
+        if self.deferred {
+            if let Some(outcome) = self.outcomes.get(self.replayed) {
+                self.replayed += 1;
+                return Ok(outcome.clone());
+            }
+        }
+
+        let action = match self.definition.get_actions().get(name) {
+            Some(action) => action,
+            None => {
+                let reason = format!("Unknown action {}", name);
+                return Err(Error::invalid(self.machine.get_name(id), None, reason));
+            }
+        };
+        let collections = self.collect(id)?;
+        let outcome = action.run(&collections).err();
+        event!(DEBUG, action = name, failed = ?outcome, "action ran");
+
+        #[cfg(feature = "async")]
+        if self.deferred {
+            self.outcomes.push(outcome.clone());
+            self.replayed += 1;
+        }
+        Ok(outcome)
+    }
+
+    fn context_input(&mut self, id: StateId, input: String) -> Result<InputStatus, Error> {
+        //submit will be true if all contexts are filled and the next state is not set
+        //if the next state is set, then the submit will be the state's submit value
+        let mut status = InputStatus {
+            state_changed: false,
+            state: None,
+            submit: false,
+            input_recognized: true,
+            reason: None,
+        };
+
+        let index = self.get_index(id);
+        let (len, reason) = match self.machine.state(id) {
+            State::ContextState(state) => (
+                state.contexts.len(),
+                state.contexts.get(index).and_then(|x| x.validate(&input)),
+            ),
+            _ => return Ok(status),
+        };
+
+        //invalid input is asked again without moving on
+        if let Some(reason) = reason {
+            status.input_recognized = false;
+            status.reason = Some(reason);
+            return Ok(status);
+        }
+
+        let progress = self.progress_mut(id);
+        if progress.index < len {
+            progress.values.insert(progress.index, input);
+            progress.index += 1;
+        }
+
+        if progress.index >= len {
+            if let Some(reason) = self.on_highest_index(id, &mut status)? {
+                status.input_recognized = false;
+                status.reason = Some(reason);
+            }
+        }
+
+        Ok(status)
+    }
+
+    fn context_output(&mut self, id: StateId) -> Result<OutputStatus, Error> {
+        let mut status = OutputStatus {
+            state_changed: false,
+            state: None,
+            submit: false,
+            output: None,
+        };
+
+        let machine = self.machine.clone();
+        let state = match machine.state(id) {
+            State::ContextState(state) => state,
+            _ => return Ok(status),
+        };
+
+        let parent = self.get_parent(id);
+        let progress = self.progress_mut(id);
+        if progress.go_back {
+            progress.go_back = false;
+            status.state_changed = true;
+            status.state = parent;
+            return Ok(status);
+        }
+
+        //skip the contexts that are not asked
+        let mut index = progress.index;
+        while index < state.contexts.len() && !self.is_context_active(id, index) {
+            self.forget(id, index);
+            index += 1;
+        }
+        self.progress_mut(id).index = index;
+
+        if index >= state.contexts.len() {
+            //the action already failed in this step and no context was left to ask again,
+            //it only runs again with the next input
+            if self.rejected.is_some() {
+                return Ok(status);
+            }
+            if self.on_highest_index(id, &mut status)?.is_none() {
+                return Ok(status);
+            }
+            //ask the last active context again, the step gives the reason
+            index = self.get_index(id);
+            if index >= state.contexts.len() {
+                return Ok(status);
+            }
+        }
+
+        let progress = self.progress_mut(id);
+        //this means that the current context is an option
+        if let Some(next_state) = state.contexts[index].output() {
+            progress.index += 1;
+            let next_state = self.resolve(next_state)?;
+            return Ok(OutputStatus {
+                state_changed: true,
+                state: Some(next_state),
+                submit: false,
+                output: None,
+            });
+        }
+
+        let output = Output::new(
+            self.render(&state.name, id, index),
+            self.render(&state.description, id, index),
+            vec![self.render(&state.contexts[index].get_name(), id, index)],
+            String::new(),
+        );
+
+        Ok(OutputStatus {
+            state_changed: false,
+            state: None,
+            submit: false,
+            output: Some(output),
+        })
+    }
+
+    fn context_back(&mut self, id: StateId) -> InputStatus {
+        let mut status = InputStatus {
+            state_changed: false,
+            state: None,
+            submit: false,
+            input_recognized: true,
+            reason: None,
+        };
+
+        if !matches!(self.machine.state(id), State::ContextState(_)) {
+            return status;
+        }
+        let parent = self.get_parent(id);
+
+        if let Some(previous) = self.previous_active(id, self.get_index(id)) {
+            self.progress_mut(id).index = previous;
+        } else if let Some(parent) = parent {
+            self.decrease_index(parent, 1);
+            status.state_changed = true;
+            status.state = Some(parent);
+        }
+
+        status
+    }
+
+    fn context_collect(&mut self, id: StateId) -> Result<Vec<Collection>, Error> {
+        let (name, len) = match self.machine.state(id) {
+            State::ContextState(state) => (state.name.clone(), state.contexts.len()),
+            _ => return Err(self.not_collectable(id)),
+        };
+        let parent = self.get_parent(id);
+
+        let active: Vec<usize> = (0..len)
+            .filter(|index| self.is_context_active(id, *index))
+            .collect();
+        let context_collections = active
+            .into_iter()
+            .map(|index| self.context_like_collect(id, index))
+            .collect::<Result<Vec<ContextLikeCollection>, Error>>()?;
+        let collection = Collection::new(name, context_collections);
+
+        if let Some(parent) = parent {
+            let mut parent_collections = self.collect(parent)?;
+            parent_collections.push(collection);
+            return Ok(parent_collections);
+        }
+
+        Ok(vec![collection])
+    }
+
+    fn context_like_collect(
+        &mut self,
+        id: StateId,
+        index: usize,
+    ) -> Result<ContextLikeCollection, Error> {
+        let machine = self.machine.clone();
+        let context = match machine.state(id) {
+            State::ContextState(state) => &state.contexts[index],
+            _ => return Err(self.not_collectable(id)),
+        };
+        let name = context.get_name();
+        let state = match context {
+            Context::StateContext(state_context) => {
+                let value = self.get_value(id, index).unwrap_or_default();
+                return Ok(ContextLikeCollection::new(
+                    name,
+                    state_context.parse(&value),
+                ));
+            }
+            Context::StateOptionsContext(state_options_context) => state_options_context.state,
+        };
+
+        let state = self.resolve(state)?;
+        let len = match self.machine.state(state) {
+            State::OptionsState(state) => state.options.len(),
+            _ => return Err(self.not_collectable(id)),
+        };
+        let index = self.get_index(state);
+        if index < len {
+            //the last option holds the context state for the other answers
+            if index == len - 1 {
+                if let Some(in_state) = self.option_state(state, index)? {
+                    if let Some(value) = self.get_value(in_state, 0) {
+                        return Ok(ContextLikeCollection::new(name, Value::Text(value)));
+                    }
+                }
+            }
+            if let State::OptionsState(state) = self.machine.state(state) {
+                return Ok(ContextLikeCollection::new(
+                    name,
+                    Value::Text(state.options[index].get_name()),
+                ));
+            }
+        }
+        //something went wrong
+        Err(self.not_collectable(id))
+    }
+
+    fn options_input(&mut self, id: StateId, input: String) -> Result<InputStatus, Error> {
+        let mut status = InputStatus {
+            state_changed: false,
+            state: None,
+            submit: false,
+            input_recognized: false,
+            reason: None,
+        };
+
+        let visible = self.visible_labels(id);
+        let option = match self.machine.state(id) {
+            State::OptionsState(state) => match state.find_option(&input, &visible) {
+                Some(index) => (index, &state.options[index]),
+                None => {
+                    status.reason = Some(format!("{} is not one of the options", input));
+                    return Ok(status);
+                }
+            },
+            _ => return Ok(status),
+        };
+        let (index, next, submit, reset) = (
+            option.0,
+            option.1.get_state(),
+            option.1.get_submit(),
+            option.1.get_reset(),
+        );
+        let (action, failure) = (
+            option.1.get_action().map(String::from),
+            option.1.get_failure(),
+        );
+
+        if let Some(action) = action {
+            //the action gets the chosen option with the other answers
+            let chosen = self.get_index(id);
+            self.progress_mut(id).index = index;
+            if let Some(reason) = self.run_action(id, &action)? {
+                if let Some(failure) = failure {
+                    status.state_changed = true;
+                    status.state = Some(self.enter(failure)?);
+                    status.input_recognized = true;
+                    return Ok(status);
+                }
+                self.progress_mut(id).index = chosen;
+                status.reason = Some(reason);
+                return Ok(status);
+            }
+        }
+
+        status.state_changed = true;
+        if let Some(next) = next {
+            let next = self.enter(next)?;
+            //if reset is defined, reset the index of the state
+            if reset {
+                self.reset_index(next);
+            }
+            status.state = Some(next);
+        }
+        status.submit = submit;
+        status.input_recognized = true;
+        self.progress_mut(id).index = index;
+
+        Ok(status)
+    }
+
+    fn options_output(&mut self, id: StateId) -> Result<OutputStatus, Error> {
+        let visible = self.visible_labels(id);
+        let output = match self.machine.state(id) {
+            State::OptionsState(state) => Output::new(
+                self.render(&state.name, id, usize::MAX),
+                self.render(&state.description, id, usize::MAX),
+                visible.into_iter().map(|(_, label)| label).collect(),
+                String::new(),
+            ),
+            _ => Output::default(),
+        };
+
+        Ok(OutputStatus {
+            state_changed: false,
+            submit: false,
+            state: None,
+            output: Some(output),
+        })
+    }
+
+    fn options_back(&mut self, id: StateId) -> InputStatus {
+        let mut status = InputStatus {
+            state_changed: false,
+            state: None,
+            submit: false,
+            input_recognized: true,
+            reason: None,
+        };
+
+        let parent = match self.machine.state(id) {
+            State::OptionsState(_) => self.get_parent(id),
+            _ => None,
+        };
+
+        if let Some(parent) = parent {
+            self.decrease_index(parent, 2);
+            status.state_changed = true;
+            status.state = Some(parent);
+        }
+
+        status
+    }
+
+    fn options_collect(&mut self, id: StateId) -> Result<Vec<Collection>, Error> {
+        let index = self.get_index(id);
+        let (name, option) = match self.machine.state(id) {
+            State::OptionsState(state) => (
+                state.name.clone(),
+                state.options.get(index).map(|x| x.get_name()),
+            ),
+            _ => return Err(self.not_collectable(id)),
+        };
+        let parent = self.get_parent(id);
+
+        if let Some(option) = option {
+            let context_like_collection = ContextLikeCollection::new(name, Value::Text(option));
+
+            let mut collection = Collection {
+                state_name: "None".to_string(),
+                context_collections: vec![context_like_collection],
+            };
+
+            if let Some(parent) = parent {
+                collection.state_name = self.machine.get_name(parent);
+                let mut parent_collections = self.collect(parent)?;
+                parent_collections.push(collection);
+                return Ok(parent_collections);
+            }
+
+            return Ok(vec![collection]);
+        }
+        //something went wrong
+        Err(self.not_collectable(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        action::Actions,
+        serde_state::SerDeState,
+        source::{LoadError, Sources},
+    };
+    use std::{
+        collections::HashMap,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    // a definition of the documents, by their path. the flow starts at main.yaml.
+    // the sources local and api both give the documents, local is the default
+    fn definition(documents: &[(&'static str, &'static str)], actions: Actions) -> Arc<Definition> {
+        let documents: Arc<HashMap<&str, &str>> = Arc::new(documents.iter().copied().collect());
+        let mut sources = Sources::new();
+        for name in ["local", "api"] {
+            let documents = documents.clone();
+            sources.add(name, move |path: &str| match documents.get(path) {
+                Some(document) => Ok(document.to_string()),
+                None => Err(LoadError::new(format!("{} does not exist", path))),
+            });
+        }
+        sources.set_default("local");
+        let mut definition =
+            SerDeState::create_from_yaml_str(sources, String::from("main.yaml"), None).unwrap();
+        definition.set_actions(actions).unwrap();
+        Arc::new(definition)
+    }
+
+    #[test]
+    fn skipped_contexts_back_to_the_same_path() {
+        let definition = definition(
+            &[(
+                "main.yaml",
+                r#"
+version: 2
+name: Skipped
+description: d
+type: !Context
+  contexts:
+  - name: q
+    type: !Normal
+    when: answered(q) and false
+  next: !Path main.yaml
+"#,
+            )],
+            Actions::new(),
+        );
+        let mut session = Session::new(definition);
+        match session.prompt() {
+            Err(Error::InvalidDefinition { state, .. }) => assert_eq!(state, "Skipped"),
+            other => panic!("{:?} is not an invalid definition", other),
+        }
+    }
+
+    #[test]
+    fn no_prompt_after_submit() {
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+        let mut actions = Actions::new();
+        actions.add("count", |_: &[Collection]| {
+            RUNS.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+        //nothing is asked, the first prompt already submits
+        let definition = definition(
+            &[(
+                "main.yaml",
+                r#"
+version: 2
+name: Nothing
+description: d
+type: !Context
+  contexts:
+  - name: q
+    type: !Normal
+    when: "false"
+  action: count
+  submit: true
+"#,
+            )],
+            actions,
+        );
+        let mut session = Session::new(definition);
+        assert!(matches!(session.prompt(), Ok(StepResult::Submitted(_))));
+        assert_eq!(RUNS.load(Ordering::SeqCst), 1);
+        assert!(matches!(session.prompt(), Err(Error::Misuse(_))));
+        assert!(matches!(
+            session.step(String::from("x")),
+            Err(Error::Misuse(_))
+        ));
+        assert_eq!(RUNS.load(Ordering::SeqCst), 1);
+    }
+
+    // the answers of every collection, in order
+    fn answers(collections: &[Collection]) -> Vec<String> {
+        collections
+            .iter()
+            .flat_map(|x| &x.context_collections)
+            .map(|x| x.value.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn loop_through_another_copy_of_a_path() {
+        //two.yaml is loaded once from local and once from api, three.yaml only once
+        let definition = definition(
+            &[
+                (
+                    "main.yaml",
+                    r#"
+version: 2
+name: Main
+description: d
+type: !Options
+- name: Start
+  state: !Path two.yaml
+"#,
+                ),
+                (
+                    "two.yaml",
+                    r#"
+version: 2
+name: Two
+description: d
+type: !Context
+  contexts:
+  - name: q
+    type: !Normal
+  - name: r
+    type: !Normal
+  next: !Path
+    path: three.yaml
+    source: local
+"#,
+                ),
+                (
+                    "three.yaml",
+                    r#"
+version: 2
+name: Submit
+description: d
+type: !Options
+- name: Yes
+  submit: true
+- name: No
+  state: !Path
+    path: two.yaml
+    source: api
+"#,
+                ),
+            ],
+            Actions::new(),
+        );
+        let mut session = Session::new(definition);
+        for input in ["Start", "a", "a", "No", "b", "b"] {
+            session.step(String::from(input)).unwrap();
+        }
+
+        //back goes to the copy the session came from
+        session.step(String::from(BACK)).unwrap();
+        let key = session.get_machine().key(session.get_state());
+        assert_eq!(key.source.as_deref(), Some("api"));
+        session.step(String::from("c")).unwrap();
+        session.step(String::from("c")).unwrap();
+
+        match session.step(String::from("Yes")) {
+            Ok(StepResult::Submitted(collections)) => {
+                assert_eq!(answers(&collections), ["Start", "a", "a", "c", "c", "Yes"])
+            }
+            other => panic!("{:?} is not submitted", other),
+        }
+    }
+
+    #[test]
+    fn eager_cycle_through_the_root() {
+        let definition = definition(
+            &[
+                (
+                    "main.yaml",
+                    r#"
+version: 2
+name: Main
+description: d
+type: !Options
+- name: Go
+  state: !Path b.yaml
+- name: Done
+  submit: true
+"#,
+                ),
+                (
+                    "b.yaml",
+                    r#"
+version: 2
+name: B
+description: d
+type: !Context
+  contexts:
+  - name: q
+    type: !Normal
+  next: !Path main.yaml
+"#,
+                ),
+            ],
+            Actions::new(),
+        );
+        //the path back to main.yaml gives the root instead of another copy of it
+        let machine = definition.get_machine();
+        let holder = machine.find_holder(Some("local"), "main.yaml").unwrap();
+        assert_eq!(machine.get_loaded(holder), Some(machine.get_root()));
+
+        let mut session = Session::new(definition);
+        session.step(String::from("Go")).unwrap();
+        session.step(String::from("a")).unwrap();
+        assert_eq!(session.get_state(), machine.get_root());
+        assert_eq!(session.get_machine().len(), machine.len());
+        match session.step(String::from("Done")) {
+            Ok(StepResult::Submitted(collections)) => {
+                assert_eq!(answers(&collections), ["a", "Done"])
+            }
+            other => panic!("{:?} is not submitted", other),
+        }
+    }
+}
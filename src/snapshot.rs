@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// the address of a state that stays the same for every machine built from the same definition.
+// StateIds depend on the order paths were loaded in, so they can not be saved
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct StateKey {
+    // the path the state was loaded from, None for the root string
+    pub path: Option<String>,
+    // the source the path was loaded from. snapshots that were taken before sources
+    // were saved have None here and match the path of any source
+    #[serde(default)]
+    pub source: Option<String>,
+    // the position of the state among the states built from that path
+    pub index: usize,
+    // only used to check that the definition did not change
+    pub name: String,
+}
+
+// what a user did in a state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProgressSnapshot {
+    pub state: StateKey,
+    // the current context of a ContextState or the chosen option of an OptionsState
+    pub index: usize,
+    pub go_back: bool,
+    // the values entered for the contexts of a ContextState, by the index of the context
+    pub values: BTreeMap<usize, String>,
+    // see Machine::get_fingerprint. None in snapshots taken before it was kept
+    #[serde(default)]
+    pub fingerprint: Option<u64>,
+}
+
+// where a user is in a flow and what they answered.
+// the way back is kept by the indexes of the states and the trail, so restoring them restores it too.
+// taken with Session::snapshot and given back to Session::restore
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionSnapshot {
+    pub state: StateKey,
+    pub submitted: bool,
+    pub progress: Vec<ProgressSnapshot>,
+    // the states the session came through from the root, see Session::get_trail.
+    // empty in snapshots taken before it was kept, the parents of the states are taken then
+    #[serde(default)]
+    pub trail: Vec<StateKey>,
+}
@@ -0,0 +1,354 @@
+use crate::{error::Error, format::Format, serde_state::SerDeState};
+use std::{collections::HashMap, error::Error as StdError, fmt, fs, io, path::PathBuf, sync::Arc};
+#[cfg(feature = "async")]
+use std::{future::Future, pin::Pin};
+
+// gives the string of a state for a path, e.g. from files, an api or a database.
+// closures are sources too, so they can capture what they need to load
+pub trait StateSource: Send + Sync {
+    fn load(&self, path: &str) -> Result<String, LoadError>;
+
+    // gives the path to load for a path written in a document this source loaded from base.
+    // base is None for paths written in documents of other sources.
+    // paths are taken as they are by default
+    fn join(&self, base: Option<&str>, path: &str) -> Result<String, LoadError> {
+        let _ = base;
+        Ok(path.to_string())
+    }
+}
+
+impl<F> StateSource for F
+where
+    F: Fn(&str) -> Result<String, LoadError> + Send + Sync,
+{
+    fn load(&self, path: &str) -> Result<String, LoadError> {
+        self(path)
+    }
+}
+
+// a source that loads without blocking, e.g. over the network.
+// closures that take the path and give back a future are async sources too
+#[cfg(feature = "async")]
+pub trait AsyncStateSource: Send + Sync {
+    fn load<'a>(&'a self, path: &'a str) -> BoxFuture<'a, Result<String, LoadError>>;
+
+    // see StateSource::join
+    fn join(&self, base: Option<&str>, path: &str) -> Result<String, LoadError> {
+        let _ = base;
+        Ok(path.to_string())
+    }
+}
+
+#[cfg(feature = "async")]
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+#[cfg(feature = "async")]
+impl<F, Fut> AsyncStateSource for F
+where
+    F: Fn(String) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<String, LoadError>> + Send + 'static,
+{
+    fn load<'a>(&'a self, path: &'a str) -> BoxFuture<'a, Result<String, LoadError>> {
+        Box::pin(self(path.to_string()))
+    }
+}
+
+#[derive(Clone)]
+enum Source {
+    Sync(Arc<dyn StateSource>),
+    #[cfg(feature = "async")]
+    Async(Arc<dyn AsyncStateSource>),
+}
+
+// the sources a definition loads its states from, by their name.
+// a path that does not name a source is loaded from the default one.
+// version 1 documents picked sources by their index, see get_name
+#[derive(Clone, Default)]
+pub struct Sources {
+    sources: HashMap<String, Source>,
+    // the names of the sources in the order they were added
+    order: Vec<String>,
+    default: Option<String>,
+    // the formats of the sources that do not go by the extensions of their paths
+    formats: HashMap<String, Format>,
+}
+
+impl Sources {
+    pub fn new() -> Sources {
+        Sources::default()
+    }
+
+    // adds a source, replacing the one with the same name
+    pub fn add(&mut self, name: impl Into<String>, source: impl StateSource + 'static) {
+        self.insert(name.into(), Source::Sync(Arc::new(source)));
+    }
+
+    // adds an async source, replacing the one with the same name.
+    // its paths can only be loaded with the async api
+    #[cfg(feature = "async")]
+    pub fn add_async(&mut self, name: impl Into<String>, source: impl AsyncStateSource + 'static) {
+        self.insert(name.into(), Source::Async(Arc::new(source)));
+    }
+
+    // a replaced source keeps the position of the one it replaces
+    fn insert(&mut self, name: String, source: Source) {
+        if !self.sources.contains_key(&name) {
+            self.order.push(name.clone());
+        }
+        self.sources.insert(name, source);
+    }
+
+    // every path of the source is read in the format, whatever its extension is
+    pub fn set_format(&mut self, name: impl Into<String>, format: Format) {
+        self.formats.insert(name.into(), format);
+    }
+
+    // gives the format of a path from the source with the name, see set_format and Format::of_path
+    pub fn get_format(&self, name: Option<&str>, path: &str) -> Result<Format, Error> {
+        let name = self.get_name(name)?;
+        Ok(self
+            .formats
+            .get(name)
+            .copied()
+            .unwrap_or_else(|| Format::of_path(path)))
+    }
+
+    // loads a path like load and reads the state in it in its format
+    pub fn load_state(&self, name: Option<&str>, path: &str) -> Result<SerDeState, Error> {
+        let string = self.load(name, path)?;
+        self.parse(name, path, &string)
+    }
+
+    // reads the state of a path that has already been loaded in its format
+    pub fn parse(&self, name: Option<&str>, path: &str, string: &str) -> Result<SerDeState, Error> {
+        self.get_format(name, path)?.parse(path, string)
+    }
+
+    pub fn set_default(&mut self, name: impl Into<String>) {
+        self.default = Some(name.into());
+    }
+
+    pub fn get_default(&self) -> Option<&str> {
+        self.default.as_deref()
+    }
+
+    // checks that there is a source with the name, or a default source if there is no name
+    pub fn check(&self, name: Option<&str>) -> Result<(), Error> {
+        self.get(name).map(|_| ())
+    }
+
+    // gives the name of the source, the default one if there is no name.
+    // version 1 documents named sources by their index, so a number that is not the name
+    // of a source gives the source that was added at that position, counting from 0
+    pub fn get_name<'a>(&'a self, name: Option<&'a str>) -> Result<&'a str, Error> {
+        let name = match name.or(self.get_default()) {
+            Some(name) => name,
+            None => return Err(Error::MissingSource(None)),
+        };
+        if self.sources.contains_key(name) {
+            return Ok(name);
+        }
+        match name.parse::<usize>().ok().and_then(|x| self.order.get(x)) {
+            Some(name) => Ok(name),
+            None => Err(Error::MissingSource(Some(name.to_string()))),
+        }
+    }
+
+    fn get(&self, name: Option<&str>) -> Result<&Source, Error> {
+        let name = self.get_name(name)?;
+        Ok(&self.sources[name])
+    }
+
+    // gives the path the source with the name loads for a path, see StateSource::join
+    pub fn join(
+        &self,
+        name: Option<&str>,
+        base: Option<&str>,
+        path: &str,
+    ) -> Result<String, Error> {
+        let joined = match self.get(name)? {
+            Source::Sync(source) => source.join(base, path),
+            #[cfg(feature = "async")]
+            Source::Async(source) => source.join(base, path),
+        };
+        joined.map_err(|error| Error::BadPath {
+            path: path.to_string(),
+            source: self.get_name(name).unwrap_or_default().to_string(),
+            reason: error.to_string(),
+        })
+    }
+
+    // loads a path from the source with the name, or from the default source if there is no name.
+    // fails with NeedsAsync if the source is an async one
+    pub fn load(&self, name: Option<&str>, path: &str) -> Result<String, Error> {
+        let string = match self.get(name)? {
+            Source::Sync(source) => source.load(path),
+            #[cfg(feature = "async")]
+            Source::Async(_) => {
+                return Err(Error::NeedsAsync {
+                    path: path.to_string(),
+                    source: name.map(String::from),
+                })
+            }
+        };
+        string.map_err(|error| self.load_error(name, path, error))
+    }
+
+    // loads a path like load, but without blocking on async sources
+    #[cfg(feature = "async")]
+    pub async fn load_async(&self, name: Option<&str>, path: &str) -> Result<String, Error> {
+        let source = self.get(name)?;
+        let string = match source {
+            Source::Sync(source) => source.load(path),
+            Source::Async(source) => source.load(path).await,
+        };
+        string.map_err(|error| self.load_error(name, path, error))
+    }
+
+    fn load_error(&self, name: Option<&str>, path: &str, error: LoadError) -> Error {
+        Error::Load {
+            path: path.to_string(),
+            source: self.get_name(name).unwrap_or_default().to_string(),
+            error,
+        }
+    }
+}
+
+// loads files below a root directory.
+// a path is relative to the file it is written in, or to the root if it starts with /.
+// paths that would leave the root are not loaded
+pub struct FileSource {
+    root: PathBuf,
+}
+
+impl FileSource {
+    pub fn new(root: impl Into<PathBuf>) -> FileSource {
+        FileSource { root: root.into() }
+    }
+}
+
+impl StateSource for FileSource {
+    // the path is joined again, so paths that did not go through join stay below the root too
+    fn load(&self, path: &str) -> Result<String, LoadError> {
+        let path = self.join(None, path)?;
+        Ok(fs::read_to_string(self.root.join(path))?)
+    }
+
+    // gives the path relative to the root, with / between its parts
+    fn join(&self, base: Option<&str>, path: &str) -> Result<String, LoadError> {
+        let mut parts: Vec<&str> = vec![];
+        if let (Some(base), false) = (base, path.starts_with('/')) {
+            //the directory of the file the path is written in
+            parts.extend(base.split('/'));
+            parts.pop();
+        }
+        for part in path.split(['/', '\\']) {
+            match part {
+                "" | "." => {}
+                ".." => {
+                    if parts.pop().is_none() {
+                        return Err(format!("{} leaves the root directory", path).into());
+                    }
+                }
+                part => parts.push(part),
+            }
+        }
+        if parts.is_empty() {
+            return Err(format!("{} is not a file", path).into());
+        }
+        Ok(parts.join("/"))
+    }
+}
+
+// why a source could not load a path
+#[derive(Debug)]
+pub struct LoadError {
+    source: Box<dyn StdError + Send + Sync>,
+}
+
+impl LoadError {
+    pub fn new(source: impl Into<Box<dyn StdError + Send + Sync>>) -> LoadError {
+        LoadError {
+            source: source.into(),
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl StdError for LoadError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> LoadError {
+        LoadError::new(error)
+    }
+}
+
+impl From<String> for LoadError {
+    fn from(error: String) -> LoadError {
+        LoadError::new(error)
+    }
+}
+
+impl From<&str> for LoadError {
+    fn from(error: &str) -> LoadError {
+        LoadError::new(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn join(base: Option<&str>, path: &str) -> Result<String, LoadError> {
+        FileSource::new("states").join(base, path)
+    }
+
+    #[test]
+    fn join_stays_below_the_root() {
+        assert!(join(None, "a/../../x.yaml").is_err());
+        assert!(join(None, "../x.yaml").is_err());
+        assert!(join(Some("main.yaml"), "../x.yaml").is_err());
+        assert!(join(Some("sub/main.yaml"), "../../x.yaml").is_err());
+        assert!(join(Some("sub/main.yaml"), "/../x.yaml").is_err());
+        assert!(join(None, "a/..").is_err());
+        assert!(join(None, "").is_err());
+    }
+
+    #[test]
+    fn join_inside_the_root() {
+        assert_eq!(join(None, "a/../x.yaml").unwrap(), "x.yaml");
+        assert_eq!(join(None, "./a/./b/../x.yaml").unwrap(), "a/x.yaml");
+        assert_eq!(join(None, "a\\x.yaml").unwrap(), "a/x.yaml");
+    }
+
+    #[test]
+    fn join_to_the_base() {
+        //relative to the directory of the file the path is written in
+        assert_eq!(join(Some("sub/main.yaml"), "x.yaml").unwrap(), "sub/x.yaml");
+        assert_eq!(
+            join(Some("sub/deeper/main.yaml"), "../x.yaml").unwrap(),
+            "sub/x.yaml"
+        );
+        assert_eq!(join(Some("sub/main.yaml"), "../x.yaml").unwrap(), "x.yaml");
+        assert_eq!(join(Some("main.yaml"), "sub/x.yaml").unwrap(), "sub/x.yaml");
+        //a leading / starts at the root whatever the base is
+        assert_eq!(join(Some("sub/main.yaml"), "/x.yaml").unwrap(), "x.yaml");
+        assert_eq!(join(None, "/sub/x.yaml").unwrap(), "sub/x.yaml");
+    }
+
+    #[test]
+    fn load_joins_too() {
+        let source = FileSource::new("states");
+        let error = source.load("../secret.yaml").unwrap_err();
+        assert!(error.to_string().contains("leaves the root directory"));
+    }
+}
@@ -1,25 +1,25 @@
-use crate::state::State;
-use parking_lot::RwLock;
-use std::sync::Arc;
+use crate::machine::StateId;
 
 pub trait StatusLike {
     fn set_state_changed(&mut self, state_changed: bool);
-    fn set_state(&mut self, state: Option<Arc<RwLock<State>>>);
+    fn set_state(&mut self, state: Option<StateId>);
     fn set_submit(&mut self, submit: bool);
 }
 
 pub struct InputStatus {
     pub state_changed: bool,
-    pub state: Option<Arc<RwLock<State>>>,
+    pub state: Option<StateId>,
     pub submit: bool,
     pub input_recognized: bool,
+    // why the input was not recognized
+    pub reason: Option<String>,
 }
 
 impl StatusLike for InputStatus {
     fn set_state_changed(&mut self, state_changed: bool) {
         self.state_changed = state_changed;
     }
-    fn set_state(&mut self, state: Option<Arc<RwLock<State>>>) {
+    fn set_state(&mut self, state: Option<StateId>) {
         self.state = state;
     }
     fn set_submit(&mut self, submit: bool) {
@@ -29,16 +29,20 @@ impl StatusLike for InputStatus {
 
 impl std::fmt::Display for InputStatus {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut name = String::new();
+        let mut state = String::from("None");
         if self.state_changed {
-            if let Some(state) = &self.state {
-                name = state.read().get_name();
+            if let Some(id) = &self.state {
+                state = id.to_string();
             }
         }
         write!(
             f,
-            "state_changed: {}\nsubmit: {}\nstate name: {}\ninput_recognized: {}",
-            self.state_changed, self.submit, name, self.input_recognized
+            "state_changed: {}\nsubmit: {}\nstate: {}\ninput_recognized: {}\nreason: {}",
+            self.state_changed,
+            self.submit,
+            state,
+            self.input_recognized,
+            self.reason.as_deref().unwrap_or("None")
         )
     }
 }
@@ -74,7 +78,7 @@ impl std::fmt::Display for Output {
 
 pub struct OutputStatus {
     pub state_changed: bool,
-    pub state: Option<Arc<RwLock<State>>>,
+    pub state: Option<StateId>,
     pub submit: bool,
     pub output: Option<Output>,
 }
@@ -83,7 +87,7 @@ impl StatusLike for OutputStatus {
     fn set_state_changed(&mut self, state_changed: bool) {
         self.state_changed = state_changed;
     }
-    fn set_state(&mut self, state: Option<Arc<RwLock<State>>>) {
+    fn set_state(&mut self, state: Option<StateId>) {
         self.state = state;
     }
     fn set_submit(&mut self, submit: bool) {
@@ -93,10 +97,10 @@ impl StatusLike for OutputStatus {
 
 impl std::fmt::Display for OutputStatus {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut name = String::new();
+        let mut state = String::from("None");
         if self.state_changed {
-            if let Some(state) = &self.state {
-                name = state.read().get_name();
+            if let Some(id) = &self.state {
+                state = id.to_string();
             }
         }
         let mut output = String::from("None");
@@ -105,8 +109,8 @@ impl std::fmt::Display for OutputStatus {
         }
         write!(
             f,
-            "state_changed: {}\nsubmit: {}\nstate name: {}\noutput: {}",
-            self.state_changed, self.submit, name, output
+            "state_changed: {}\nsubmit: {}\nstate: {}\noutput: {}",
+            self.state_changed, self.submit, state, output
         )
     }
 }
@@ -0,0 +1,24 @@
+use crate::value::Value;
+
+// fills the placeholders of a text, e.g. "Hello {{first_name}}!".
+// a placeholder holds the name of a context or of an OptionsState, spaces around it do not matter.
+// answer gives what the user answered for a name, placeholders without an answer become empty
+pub fn render(text: &str, answer: &impl Fn(&str) -> Option<Value>) -> String {
+    let mut rendered = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start + 2..].find("}}") {
+            Some(end) => start + 2 + end,
+            //not a placeholder
+            None => break,
+        };
+        rendered.push_str(&rest[..start]);
+        let name = rest[start + 2..end].trim();
+        if let Some(value) = answer(name) {
+            rendered.push_str(&value.to_string());
+        }
+        rest = &rest[end + 2..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
@@ -0,0 +1,13 @@
+// events about building and running flows, given to tracing with the tracing feature.
+// without it the events are left out and their fields are not evaluated.
+// the level is one of tracing's levels, e.g. event!(DEBUG, path = %path, "loaded")
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        {
+            tracing::event!(tracing::Level::$level, $($arg)+);
+        }
+    };
+}
+
+pub(crate) use event;
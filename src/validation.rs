@@ -0,0 +1,86 @@
+use regex::Regex;
+
+// a rule the input of a StateContext has to follow.
+// input that breaks a rule is rejected and the context is asked again
+#[derive(Clone)]
+pub enum Rule {
+    Required,
+    MinLength(usize),
+    MaxLength(usize),
+    Regex(Regex),
+    IntegerRange(Option<i64>, Option<i64>),
+    DecimalRange(Option<f64>, Option<f64>),
+    OneOf(Vec<String>),
+}
+
+impl Rule {
+    // gives the reason if the input breaks the rule.
+    // empty input only breaks Required, other rules are for what has been entered
+    pub fn check(&self, input: &str) -> Option<String> {
+        if input.is_empty() {
+            if let Rule::Required = self {
+                return Some(String::from("A value is required"));
+            }
+            return None;
+        }
+
+        match self {
+            Rule::Required => None,
+            Rule::MinLength(min) => {
+                if input.chars().count() < *min {
+                    return Some(format!("Must be at least {} characters long", min));
+                }
+                None
+            }
+            Rule::MaxLength(max) => {
+                if input.chars().count() > *max {
+                    return Some(format!("Must be at most {} characters long", max));
+                }
+                None
+            }
+            Rule::Regex(regex) => {
+                if !regex.is_match(input) {
+                    return Some(String::from("Does not have the right format"));
+                }
+                None
+            }
+            Rule::IntegerRange(min, max) => match input.trim().parse::<i64>() {
+                Ok(value) => check_range(value, *min, *max),
+                Err(_) => Some(String::from("Must be a whole number")),
+            },
+            Rule::DecimalRange(min, max) => match input.trim().parse::<f64>() {
+                Ok(value) if value.is_finite() => check_range(value, *min, *max),
+                _ => Some(String::from("Must be a number")),
+            },
+            Rule::OneOf(values) => {
+                if !values.iter().any(|x| x == input) {
+                    return Some(format!("Must be one of: {}", values.join(", ")));
+                }
+                None
+            }
+        }
+    }
+}
+
+fn check_range<T: PartialOrd + std::fmt::Display>(
+    value: T,
+    min: Option<T>,
+    max: Option<T>,
+) -> Option<String> {
+    if let Some(min) = min {
+        if value < min {
+            return Some(format!("Must be at least {}", min));
+        }
+    }
+    if let Some(max) = max {
+        if value > max {
+            return Some(format!("Must be at most {}", max));
+        }
+    }
+    None
+}
+
+// checks the input against all the rules and gives the reason of the first one it breaks
+pub fn check_all(rules: &[Rule], input: &str) -> Option<String> {
+    rules.iter().find_map(|x| x.check(input))
+}
@@ -0,0 +1,109 @@
+use chrono::{NaiveDate, NaiveTime};
+use std::fmt;
+use url::Url;
+
+// what a StateContext expects the user to enter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Text,
+    Integer,
+    Decimal,
+    Boolean,
+    Date,
+    Time,
+    Email,
+    Url,
+}
+
+// a collected answer, parsed by the kind of its context
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Text(String),
+    Integer(i64),
+    Decimal(f64),
+    Boolean(bool),
+    Date(NaiveDate),
+    Time(NaiveTime),
+    Email(String),
+    Url(Url),
+    // nothing was entered for a context that is not Text
+    Empty,
+}
+
+impl Kind {
+    // parses the input or gives the reason it could not be parsed
+    pub fn parse(&self, input: &str) -> Result<Value, String> {
+        if let Kind::Text = self {
+            return Ok(Value::Text(input.to_string()));
+        }
+
+        let input = input.trim();
+        if input.is_empty() {
+            return Ok(Value::Empty);
+        }
+
+        match self {
+            Kind::Text => Ok(Value::Text(input.to_string())),
+            Kind::Integer => input
+                .parse::<i64>()
+                .map(Value::Integer)
+                .map_err(|_| String::from("Must be a whole number")),
+            Kind::Decimal => match input.parse::<f64>() {
+                Ok(value) if value.is_finite() => Ok(Value::Decimal(value)),
+                _ => Err(String::from("Must be a number")),
+            },
+            Kind::Boolean => match input.to_lowercase().as_str() {
+                "yes" | "y" | "true" => Ok(Value::Boolean(true)),
+                "no" | "n" | "false" => Ok(Value::Boolean(false)),
+                _ => Err(String::from("Must be yes or no")),
+            },
+            Kind::Date => NaiveDate::parse_from_str(input, "%Y-%m-%d")
+                .map(Value::Date)
+                .map_err(|_| String::from("Must be a date like 2022-12-31")),
+            Kind::Time => NaiveTime::parse_from_str(input, "%H:%M:%S")
+                .or_else(|_| NaiveTime::parse_from_str(input, "%H:%M"))
+                .map(Value::Time)
+                .map_err(|_| String::from("Must be a time like 13:30")),
+            Kind::Email => {
+                if is_email(input) {
+                    return Ok(Value::Email(input.to_string()));
+                }
+                Err(String::from("Must be an email address"))
+            }
+            Kind::Url => Url::parse(input)
+                .map(Value::Url)
+                .map_err(|_| String::from("Must be a URL like https://example.com")),
+        }
+    }
+}
+
+// only checks the shape of the address: something@domain.tld without spaces
+fn is_email(input: &str) -> bool {
+    if input.chars().any(char::is_whitespace) {
+        return false;
+    }
+    match input.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && domain.split('.').all(|x| !x.is_empty())
+        }
+        None => false,
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Text(value) | Value::Email(value) => write!(f, "{}", value),
+            Value::Integer(value) => write!(f, "{}", value),
+            Value::Decimal(value) => write!(f, "{}", value),
+            Value::Boolean(value) => write!(f, "{}", if *value { "yes" } else { "no" }),
+            Value::Date(value) => write!(f, "{}", value.format("%Y-%m-%d")),
+            Value::Time(value) => write!(f, "{}", value.format("%H:%M:%S")),
+            Value::Url(value) => write!(f, "{}", value),
+            Value::Empty => Ok(()),
+        }
+    }
+}